@@ -0,0 +1,418 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Application level shipper configuration from the `config` section of `MAKE87.yml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ShipperConfig {
+    pub ship_all: ShipAllConfig,
+    pub connection: ConnectionConfig,
+    pub spool: SpoolConfig,
+    pub headless: HeadlessConfig,
+    pub server: ServerConfig,
+    pub fallback: FallbackConfig,
+    pub workers: WorkerConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+impl ShipperConfig {
+    pub fn from_value(value: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+        serde_json::from_value(value.clone())
+    }
+}
+
+/// Shipper settings in the config of a zenoh subscriber, next to the `handler` settings
+/// consumed by make87.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SubscriberConfig {
+    /// Message type used for every sample instead of resolving it from the encoding,
+    /// attachment or topic key
+    pub message_type: Option<String>,
+    pub entity_path: EntityPathConfig,
+    pub filter: FilterConfig,
+    /// Upper bound on samples shipped per second and topic, newer samples replacing
+    /// held back ones
+    pub max_rate_hz: Option<f64>,
+    /// Ship only every n-th sample of a topic
+    pub keep_every_nth: Option<u64>,
+    pub timelines: TimelineConfig,
+    /// Log receive time minus header timestamp as `<entity_path>/latency_ms`
+    pub latency: bool,
+    /// JSON or YAML file mapping class ids of detections to names and colors
+    pub label_map: Option<String>,
+    pub detections: DetectionConfig,
+}
+
+impl SubscriberConfig {
+    pub fn from_map(
+        config: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(serde_json::Value::Object(
+            config.clone().into_iter().collect(),
+        ))
+    }
+}
+
+/// Rewriting of the entity paths a subscriber logs to.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EntityPathConfig {
+    /// Template such as `{system}/{node}/{entity_path}`
+    pub template: Option<String>,
+    pub replace: Vec<ReplaceRule>,
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplaceRule {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+/// Timelines set for every message.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimelineConfig {
+    /// Timestamp in the message header
+    pub header_time: bool,
+    /// Sources tried in order for `header_time`, with receive time as last resort
+    pub time_sources: Vec<TimeSource>,
+    /// Wall-clock time the sample was received at
+    pub receive_time: bool,
+    /// Hybrid logical clock timestamp zenoh attached to the sample
+    pub zenoh_time: bool,
+    /// Per-topic count of received samples
+    pub sequence: bool,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> Self {
+        Self {
+            header_time: true,
+            time_sources: vec![TimeSource::Header, TimeSource::Zenoh, TimeSource::Receive],
+            receive_time: false,
+            zenoh_time: false,
+            sequence: false,
+        }
+    }
+}
+
+/// Where the time of a message is taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// Timestamp in the message header
+    Header,
+    /// Hybrid logical clock timestamp zenoh attached to the sample
+    Zenoh,
+    /// Wall-clock time the sample was received at
+    Receive,
+}
+
+impl TimeSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeSource::Header => "header",
+            TimeSource::Zenoh => "zenoh",
+            TimeSource::Receive => "receive",
+        }
+    }
+}
+
+/// Selection of the detections shipped per message.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    /// Detections with a lower confidence are dropped
+    pub min_confidence: Option<f32>,
+    /// Only detections of these classes are kept, all if empty
+    pub classes: Vec<ClassSelector>,
+    /// Only the most confident detections are kept
+    pub top_k: Option<usize>,
+    pub coordinates: BoxCoordinates,
+    /// Entity path of the image whose most recent dimensions scale normalized coordinates
+    pub image_entity_path: Option<String>,
+    /// Width and height scaling normalized coordinates until an image was logged
+    pub image_size: Option<[u32; 2]>,
+}
+
+/// Coordinate system boxes are published in.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoxCoordinates {
+    /// Pixels of the image
+    #[default]
+    Pixel,
+    /// Fractions of the image size in [0, 1]
+    Normalized,
+    /// Normalized if all coordinates of a message are within [0, 1] and an image size is
+    /// known, pixels otherwise
+    Auto,
+}
+
+/// A class given by id or by its name in the topic's label map.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ClassSelector {
+    Id(u16),
+    Name(String),
+}
+
+/// Samples dropped before decoding, by message type and by entity path after rewriting.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub message_types: PatternList,
+    pub entity_paths: PatternList,
+}
+
+/// Glob patterns, or regular expressions when prefixed with `re:`. With any `include`
+/// patterns only matching values pass; values matching an `exclude` pattern never pass.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PatternList {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+/// Shipper settings in the config of the `rerun-grpc-client` client, next to the
+/// `batcher_config` consumed by make87.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RerunClientConfig {
+    pub file_sink: FileSinkConfig,
+    /// Timeout in seconds for flushing at shutdown
+    pub flush_timeout: f64,
+}
+
+impl Default for RerunClientConfig {
+    fn default() -> Self {
+        Self {
+            file_sink: FileSinkConfig::default(),
+            flush_timeout: 3.0,
+        }
+    }
+}
+
+impl RerunClientConfig {
+    pub fn from_map(
+        config: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(serde_json::Value::Object(
+            config.clone().into_iter().collect(),
+        ))
+    }
+}
+
+/// Discovery of publishers through zenoh liveliness tokens.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShipAllConfig {
+    pub enabled: bool,
+    /// Liveliness key expression watched for publisher tokens
+    pub liveliness_key_expr: String,
+    /// Ring buffer capacity of each discovered topic's subscriber
+    pub capacity: usize,
+}
+
+impl Default for ShipAllConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            liveliness_key_expr: "@adv/pub/**".to_string(),
+            capacity: 10,
+        }
+    }
+}
+
+/// Supervision of the Rerun gRPC connection. All durations are in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    pub check_interval: f64,
+    pub initial_backoff: f64,
+    pub max_backoff: f64,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: 2.0,
+            initial_backoff: 0.5,
+            max_backoff: 30.0,
+        }
+    }
+}
+
+/// Store-and-forward spooling to disk while the viewer is unreachable.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    pub enabled: bool,
+    pub directory: String,
+    /// Upper bound for the whole spool, the oldest segments are dropped beyond it
+    pub max_size_bytes: u64,
+    pub segment_size_bytes: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "/tmp/rerun-spool".to_string(),
+            max_size_bytes: 1024 * 1024 * 1024,
+            segment_size_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Local `.rrd` recording alongside the gRPC stream.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FileSinkConfig {
+    pub enabled: bool,
+    pub directory: String,
+    pub max_file_size_bytes: u64,
+    /// Maximum age of a file in seconds before a new one is started
+    pub max_file_duration: f64,
+    /// Number of files kept, the oldest are deleted beyond it
+    pub max_files: usize,
+}
+
+impl Default for FileSinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "/tmp/rerun-recordings".to_string(),
+            max_file_size_bytes: 256 * 1024 * 1024,
+            max_file_duration: 600.0,
+            max_files: 20,
+        }
+    }
+}
+
+/// Recording without any viewer connection, in place of the `rerun-grpc` client.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HeadlessConfig {
+    pub enabled: bool,
+    pub sink: HeadlessSink,
+    /// Upper bound for the memory sink, the oldest data is dropped beyond it
+    pub max_memory_bytes: u64,
+    /// Rotation of the recorded files. Memory dumps are written to the same directory.
+    pub file_sink: FileSinkConfig,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sink: HeadlessSink::default(),
+            max_memory_bytes: 1024 * 1024 * 1024,
+            file_sink: FileSinkConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeadlessSink {
+    /// Write to rotating `.rrd` files
+    #[default]
+    File,
+    /// Keep the recording in memory and write it to disk on SIGUSR1
+    Memory,
+}
+
+/// Hosting a Rerun gRPC server, and optionally the web viewer, in place of the
+/// `rerun-grpc` client.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub enabled: bool,
+    pub bind_ip: String,
+    pub grpc_port: u16,
+    /// Host name or IP under which viewers reach this device. Required with the web viewer,
+    /// which hands it to the browser as the gRPC address to connect to.
+    pub advertised_host: Option<String>,
+    /// Memory limit of the server-side buffer, e.g. `25%` or `2GB`
+    pub memory_limit: String,
+    pub web_viewer: bool,
+    pub web_viewer_port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_ip: "0.0.0.0".to_string(),
+            grpc_port: 9876,
+            advertised_host: None,
+            memory_limit: "25%".to_string(),
+            web_viewer: false,
+            web_viewer_port: 9090,
+        }
+    }
+}
+
+/// Generic shipping of message types without a registered handler.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    pub enabled: bool,
+    /// Bytes shown per field and of any trailing non-protobuf data in the dump
+    pub max_dump_bytes: usize,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_dump_bytes: 64,
+        }
+    }
+}
+
+/// Pool of workers decoding and logging samples off the receive tasks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WorkerConfig {
+    /// Number of workers, 0 decodes on the receive tasks
+    pub count: usize,
+    /// Samples queued per worker before receiving waits
+    pub queue_capacity: usize,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            queue_capacity: 64,
+        }
+    }
+}
+
+/// Health of the shipper itself, logged into the recording.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// Reserved entity path everything is logged under
+    pub entity_path: String,
+    /// Seconds between reports
+    pub interval: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            entity_path: "/_shipper".to_string(),
+            interval: 1.0,
+        }
+    }
+}
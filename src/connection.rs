@@ -0,0 +1,289 @@
+use crate::config::ConnectionConfig;
+use crate::file_sink::{RotatingFileSink, TeeSink};
+use crate::label_map::Annotations;
+use crate::spool::Spool;
+use crate::telemetry::Telemetry;
+use make87::interfaces::rerun::RerunGRpcInterface;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+pub const RERUN_CLIENT_NAME: &str = "rerun-grpc-client";
+
+/// Recording id derived from the system id the same way make87 does, so every mode of
+/// the same device logs into the same recording
+fn recording_id(system_id: &str) -> Uuid {
+    let hash = Sha256::digest(system_id.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    bytes[6] = (bytes[6] & 0x0F) | 0x40; // Version 4
+    bytes[8] = (bytes[8] & 0x3F) | 0x80; // Variant RFC 4122
+    Uuid::from_bytes(bytes)
+}
+
+/// Builder for recording streams the shipper builds itself, matching the application and
+/// recording ids of the streams make87 builds for the rerun-grpc client
+pub fn recording_builder(system_id: &str) -> rerun::RecordingStreamBuilder {
+    rerun::RecordingStreamBuilder::new(system_id).recording_id(recording_id(system_id).to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Connecting,
+    Disconnected,
+}
+
+enum SinkProbe {
+    Grpc(rerun::sink::GrpcSinkConnectionState),
+    Tee(rerun::RecordingStream),
+}
+
+/// Query the state of the gRPC sink behind a recording stream
+fn grpc_connection_state(rec: &rerun::RecordingStream) -> ConnectionState {
+    let (tx, rx) = mpsc::channel();
+
+    rec.inspect_sink(move |sink| {
+        if let Some(grpc_sink) = sink.as_any().downcast_ref::<rerun::sink::GrpcSink>() {
+            let _ = tx.send(SinkProbe::Grpc(grpc_sink.status()));
+        } else if let Some(tee_sink) = sink.as_any().downcast_ref::<TeeSink>() {
+            let _ = tx.send(SinkProbe::Tee(tee_sink.transport().clone()));
+        }
+    });
+
+    // Check with a short timeout
+    match rx.recv_timeout(Duration::from_millis(100)) {
+        Ok(SinkProbe::Grpc(status)) => match status {
+            rerun::sink::GrpcSinkConnectionState::Connected => ConnectionState::Connected,
+            rerun::sink::GrpcSinkConnectionState::Connecting => ConnectionState::Connecting,
+            rerun::sink::GrpcSinkConnectionState::Disconnected(_) => ConnectionState::Disconnected,
+        },
+        Ok(SinkProbe::Tee(transport)) => grpc_connection_state(&transport),
+        // Timeout or no status - assume disconnected
+        Err(_) => ConnectionState::Disconnected,
+    }
+}
+
+/// The stream carrying `rec` to the viewer: the transport of a tee into local files, or
+/// `rec` itself
+pub fn transport_stream(rec: &rerun::RecordingStream) -> rerun::RecordingStream {
+    let (tx, rx) = mpsc::channel();
+
+    rec.inspect_sink(move |sink| {
+        if let Some(tee_sink) = sink.as_any().downcast_ref::<TeeSink>() {
+            let _ = tx.send(tee_sink.transport().clone());
+        }
+    });
+
+    rx.recv_timeout(Duration::from_millis(100))
+        .unwrap_or_else(|_| rec.clone())
+}
+
+/// Builds the recording streams handed to the handler tasks.
+pub struct Connector {
+    interface: RerunGRpcInterface,
+    files: Option<Arc<RotatingFileSink>>,
+}
+
+impl Connector {
+    pub fn new(interface: RerunGRpcInterface, files: Option<Arc<RotatingFileSink>>) -> Self {
+        Self { interface, files }
+    }
+
+    /// Connect to the viewer. With local files configured, the returned stream tees into
+    /// them and uses a second client stream only as transport to the viewer.
+    pub fn connect(&self) -> Result<rerun::RecordingStream, Box<dyn Error + Send + Sync>> {
+        let rec = self
+            .interface
+            .get_client_recording_stream(RERUN_CLIENT_NAME)?;
+        if let Some(files) = &self.files {
+            let transport = self
+                .interface
+                .get_client_recording_stream(RERUN_CLIENT_NAME)?;
+            rec.set_sink(Box::new(TeeSink::new(transport, files.clone())));
+        }
+        Ok(rec)
+    }
+}
+
+/// Recording stream shared by all handler tasks.
+///
+/// Handler tasks only read the current stream; the connection supervisor swaps in a new
+/// one after a reconnect.
+pub struct SharedRecordingStream {
+    rec: RwLock<rerun::RecordingStream>,
+}
+
+impl SharedRecordingStream {
+    pub fn new(rec: rerun::RecordingStream) -> Self {
+        Self {
+            rec: RwLock::new(rec),
+        }
+    }
+
+    pub fn get(&self) -> rerun::RecordingStream {
+        self.rec.read().unwrap().clone()
+    }
+
+    /// Swap in a new recording stream, returning the previous one
+    fn replace(&self, rec: rerun::RecordingStream) -> rerun::RecordingStream {
+        std::mem::replace(&mut *self.rec.write().unwrap(), rec)
+    }
+
+    /// Disconnect the current stream, let `drain` log into `rec` and then swap `rec` in.
+    ///
+    /// Handler tasks wait on the lock meanwhile, so everything logged before the swap is
+    /// drained before anything logged after it.
+    fn replace_after_drain(
+        &self,
+        rec: rerun::RecordingStream,
+        drain: impl FnOnce(&rerun::RecordingStream),
+    ) {
+        let mut current = self.rec.write().unwrap();
+        current.disconnect();
+        drain(&rec);
+        *current = rec;
+    }
+}
+
+/// Spread reconnect attempts by up to +/-25% so several shippers don't retry in lockstep
+fn with_jitter(backoff: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let factor = 0.75 + 0.5 * (nanos as f64 / 1_000_000_000.0);
+    backoff.mul_f64(factor)
+}
+
+/// Wait up to `timeout` for a freshly built recording stream to reach the viewer
+async fn wait_until_connected(rec: &rerun::RecordingStream, timeout: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let probe = rec.clone();
+        let state = tokio::task::spawn_blocking(move || grpc_connection_state(&probe))
+            .await
+            .unwrap_or(ConnectionState::Disconnected);
+        match state {
+            ConnectionState::Connected => return true,
+            ConnectionState::Disconnected => return false,
+            ConnectionState::Connecting if tokio::time::Instant::now() >= deadline => return false,
+            ConnectionState::Connecting => tokio::time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+}
+
+/// Watch the gRPC sink of the shared recording stream independently of message traffic and
+/// reconnect with exponential backoff whenever the connection is lost.
+///
+/// With a spool, data logged while disconnected is written to disk and replayed in order
+/// once a new connection is established. Segments left over from a previous run are
+/// replayed as soon as the viewer is reachable.
+pub async fn run_connection_supervisor(
+    connector: Connector,
+    shared: Arc<SharedRecordingStream>,
+    config: ConnectionConfig,
+    mut spool: Option<Spool>,
+    telemetry: Arc<Telemetry>,
+    annotations: Arc<Annotations>,
+) {
+    let check_interval = Duration::from_secs_f64(config.check_interval);
+    let initial_backoff = Duration::from_secs_f64(config.initial_backoff);
+    let max_backoff = Duration::from_secs_f64(config.max_backoff);
+
+    let connector = Arc::new(connector);
+    let mut backoff = initial_backoff;
+    let mut next_check = check_interval;
+
+    loop {
+        tokio::time::sleep(next_check).await;
+
+        let rec = shared.get();
+        let state = tokio::task::spawn_blocking(move || grpc_connection_state(&rec))
+            .await
+            .unwrap_or(ConnectionState::Disconnected);
+        telemetry.record_connection_state(state);
+
+        match state {
+            ConnectionState::Connected => {
+                // Segments left over from a previous run
+                if let Some(spool) = spool
+                    .as_mut()
+                    .filter(|spool| !spool.is_active() && spool.has_pending())
+                {
+                    tokio::task::block_in_place(|| spool.replay_closed(&shared.get()));
+                }
+                backoff = initial_backoff;
+                next_check = check_interval;
+            }
+            ConnectionState::Connecting => {
+                next_check = check_interval;
+            }
+            ConnectionState::Disconnected => {
+                if let Some(spool) = spool.as_mut() {
+                    tokio::task::block_in_place(|| spool.on_disconnected(&shared.get()));
+                }
+
+                log::warn!("gRPC connection lost, attempting to reconnect...");
+                let reconnect_connector = connector.clone();
+                let reconnected = tokio::task::spawn_blocking(move || {
+                    reconnect_connector.connect().map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result);
+
+                match reconnected {
+                    Ok(new_rec) if spool.as_ref().is_some_and(Spool::is_active) => {
+                        // Only hand the spool over once the new connection is confirmed
+                        if wait_until_connected(&new_rec, check_interval).await {
+                            if let Some(spool) = spool.as_mut() {
+                                tokio::task::block_in_place(|| {
+                                    spool.replay_closed(&new_rec);
+                                    shared.replace_after_drain(new_rec, |rec| spool.finish(rec));
+                                });
+                            }
+                            annotations.relog(&shared.get());
+                            telemetry.record_reconnect();
+                            log::info!("Successfully reconnected to gRPC server");
+                        } else {
+                            new_rec.disconnect();
+                            log::error!("Failed to reconnect to gRPC server: viewer unreachable");
+                        }
+                    }
+                    Ok(new_rec) => {
+                        // Gracefully disconnect the old connection
+                        let old_rec = shared.replace(new_rec);
+                        old_rec.disconnect();
+                        annotations.relog(&shared.get());
+                        telemetry.record_reconnect();
+                        log::info!("Successfully reconnected to gRPC server");
+                    }
+                    Err(e) => {
+                        log::error!("Failed to reconnect to gRPC server: {}", e);
+                        // Continue with old connection, might recover
+                    }
+                }
+
+                next_check = with_jitter(backoff);
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_id_is_deterministic_uuid_v4() {
+        let id = recording_id("test_system_id");
+        assert_eq!(id, recording_id("test_system_id"));
+        assert_ne!(id, recording_id("other_system_id"));
+        assert_eq!(id.get_version_num(), 4);
+        assert_eq!(id.get_variant(), uuid::Variant::RFC4122);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::config::{ShipAllConfig, SubscriberConfig};
+use crate::message_handlers::{MessageDispatcher, ReceivedSample};
+use crate::{handle_sample, ShipperContext};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use tokio::task::{AbortHandle, JoinSet};
+use zenoh::handlers::RingChannel;
+use zenoh::key_expr::KeyExpr;
+use zenoh::sample::SampleKind;
+use zenoh::Session;
+
+struct DiscoveredTopic {
+    task: AbortHandle,
+    // Liveliness tokens currently announcing this topic
+    tokens: HashSet<String>,
+}
+
+/// Extracts the topic key announced by a liveliness token.
+///
+/// Advanced publishers announce themselves as `@adv/pub/<zid>/<eid>/<meta>/@/<topic_key>`,
+/// plain tokens are taken to be the topic key itself.
+fn topic_key_from_token(token: &str) -> &str {
+    match token.rfind("/@/") {
+        Some(pos) => &token[pos + 3..],
+        None => token,
+    }
+}
+
+/// Watches liveliness tokens for publishers of `make87_messages-<Type>` topics and ships
+/// each topic while at least one of its tokens is alive.
+///
+/// Topics already covered by a configured subscriber are left to that subscriber.
+pub async fn run_discovery(
+    session: Session,
+    config: ShipAllConfig,
+    configured_key_exprs: Vec<String>,
+    ctx: ShipperContext,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let configured_key_exprs = configured_key_exprs
+        .into_iter()
+        .map(KeyExpr::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tokens = session
+        .liveliness()
+        .declare_subscriber(config.liveliness_key_expr.as_str())
+        .history(true)
+        .await?;
+    log::info!(
+        "Discovering topics from liveliness tokens on {}",
+        config.liveliness_key_expr
+    );
+
+    let mut topics: HashMap<String, DiscoveredTopic> = HashMap::new();
+    // Dropping the set aborts all topic tasks when discovery itself is stopped
+    let mut topic_tasks = JoinSet::new();
+
+    while let Ok(token) = tokens.recv_async().await {
+        // Forget topics whose subscriber closed so their next token subscribes again
+        while let Some(result) = topic_tasks.try_join_next_with_id() {
+            let Ok((id, topic_key)) = result else {
+                continue;
+            };
+            if topics
+                .get(&topic_key)
+                .is_some_and(|topic| topic.task.id() == id)
+            {
+                topics.remove(&topic_key);
+                log::info!("Subscriber closed, stopped shipping topic {}", topic_key);
+            }
+        }
+
+        let token_key = token.key_expr().to_string();
+        let topic_key = topic_key_from_token(&token_key).to_string();
+        if ctx
+            .registry
+            .extract_message_type_from_topic_key(&topic_key)
+            .is_none()
+        {
+            continue;
+        }
+
+        match token.kind() {
+            SampleKind::Put => {
+                if let Some(topic) = topics.get_mut(&topic_key) {
+                    topic.tokens.insert(token_key);
+                    continue;
+                }
+
+                let Ok(topic_key_expr) = KeyExpr::try_from(topic_key.clone()) else {
+                    log::warn!(
+                        "Ignoring invalid topic key from liveliness token: {}",
+                        token_key
+                    );
+                    continue;
+                };
+                if configured_key_exprs
+                    .iter()
+                    .any(|key_expr| key_expr.includes(&topic_key_expr))
+                {
+                    continue;
+                }
+
+                let subscriber = match session
+                    .declare_subscriber(topic_key_expr)
+                    .with(RingChannel::new(config.capacity))
+                    .await
+                {
+                    Ok(subscriber) => subscriber,
+                    Err(e) => {
+                        log::error!(
+                            "Failed to subscribe to discovered topic {}: {}",
+                            topic_key,
+                            e
+                        );
+                        continue;
+                    }
+                };
+
+                log::info!("Discovered topic {}", topic_key);
+                let mut dispatcher = MessageDispatcher::new(&ctx, SubscriberConfig::default())?;
+                let ctx = ctx.clone();
+                let task_topic_key = topic_key.clone();
+                let task = topic_tasks.spawn(async move {
+                    while let Ok(sample) = subscriber.recv_async().await {
+                        let sample = ReceivedSample::now(sample);
+                        handle_sample(&mut dispatcher, sample, &ctx).await;
+                    }
+                    task_topic_key
+                });
+
+                topics.insert(
+                    topic_key,
+                    DiscoveredTopic {
+                        task,
+                        tokens: HashSet::from([token_key]),
+                    },
+                );
+            }
+            SampleKind::Delete => {
+                let Some(topic) = topics.get_mut(&topic_key) else {
+                    continue;
+                };
+                topic.tokens.remove(&token_key);
+                if topic.tokens.is_empty() {
+                    // Aborting the task drops and thereby undeclares the subscriber
+                    if let Some(topic) = topics.remove(&topic_key) {
+                        topic.task.abort();
+                    }
+                    log::info!("Publisher left, stopped shipping topic {}", topic_key);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topic_key_from_advanced_publisher_token() {
+        assert_eq!(
+            topic_key_from_token(
+                "@adv/pub/1f2e3d4c/12/_/_/@/make87_messages-image-compressed-ImageJPEG/camera"
+            ),
+            "make87_messages-image-compressed-ImageJPEG/camera"
+        );
+    }
+
+    #[test]
+    fn topic_key_from_plain_token() {
+        assert_eq!(
+            topic_key_from_token("make87_messages-core-Header"),
+            "make87_messages-core-Header"
+        );
+    }
+}
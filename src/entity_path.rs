@@ -0,0 +1,239 @@
+use crate::config::EntityPathConfig;
+use crate::topic_key::TopicKey;
+use regex::Regex;
+
+/// Values available to entity path templates.
+pub struct PathVariables<'a> {
+    pub entity_path: &'a str,
+    pub topic_key: Option<TopicKey<'a>>,
+    pub message_type: &'a str,
+    pub reference_id: Option<String>,
+}
+
+/// Compiled entity path rewrite rules of a subscriber.
+///
+/// The template is rendered first, then the regex replacements are applied in order and
+/// finally the prefix is prepended.
+#[derive(Default)]
+pub struct EntityPathRules {
+    template: Option<String>,
+    replace: Vec<(Regex, String)>,
+    prefix: Option<String>,
+}
+
+impl EntityPathRules {
+    pub fn new(config: &EntityPathConfig) -> Result<Self, regex::Error> {
+        let replace = config
+            .replace
+            .iter()
+            .map(|rule| Ok((Regex::new(&rule.pattern)?, rule.replacement.clone())))
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+        Ok(Self {
+            template: config.template.clone(),
+            replace,
+            prefix: config.prefix.clone(),
+        })
+    }
+
+    /// Longest path that every rendered path lies under, known before any message arrives.
+    ///
+    /// The header values are never known in advance, the topic key and message type only
+    /// when given. Without a template the path comes from the header, so only the prefix is
+    /// known, and the root if there is none.
+    pub fn known_ancestor(
+        &self,
+        topic_key: Option<TopicKey>,
+        message_type: Option<&str>,
+    ) -> String {
+        let Some(template) = &self.template else {
+            return self.with_prefix(String::new());
+        };
+        let mut unknown = vec!["{entity_path}", "{reference_id}"];
+        if topic_key.is_none() {
+            unknown.extend(["{system}", "{node}", "{topic}", "{version}"]);
+        }
+        if message_type.is_none() {
+            unknown.push("{message_type}");
+        }
+        let vars = PathVariables {
+            entity_path: "",
+            topic_key,
+            message_type: message_type.unwrap_or_default(),
+            reference_id: None,
+        };
+
+        match unknown.iter().filter_map(|name| template.find(name)).min() {
+            None => self.apply(&vars),
+            // Replacements may rewrite any part of the path
+            Some(_) if !self.replace.is_empty() => self.with_prefix(String::new()),
+            Some(end) => {
+                // Only whole segments before the first unknown value are known
+                let known = &template[..template[..end].rfind('/').unwrap_or(0)];
+                self.with_prefix(render_template(known, &vars))
+            }
+        }
+    }
+
+    pub fn apply(&self, vars: &PathVariables) -> String {
+        let mut entity_path = match &self.template {
+            Some(template) => render_template(template, vars),
+            None => vars.entity_path.to_string(),
+        };
+        for (pattern, replacement) in &self.replace {
+            entity_path = pattern
+                .replace_all(&entity_path, replacement.as_str())
+                .into_owned();
+        }
+        self.with_prefix(entity_path)
+    }
+
+    fn with_prefix(&self, mut entity_path: String) -> String {
+        if let Some(prefix) = &self.prefix {
+            entity_path = match entity_path.trim_start_matches('/') {
+                "" => prefix.trim_end_matches('/').to_string(),
+                rest => format!("{}/{}", prefix.trim_end_matches('/'), rest),
+            };
+        }
+
+        if entity_path.starts_with('/') {
+            entity_path
+        } else {
+            format!("/{}", entity_path)
+        }
+    }
+}
+
+/// Substitute `{entity_path}`, `{system}`, `{node}`, `{topic}`, `{version}`,
+/// `{message_type}` and `{reference_id}`. Unavailable values render empty.
+fn render_template(template: &str, vars: &PathVariables) -> String {
+    let topic_key = vars.topic_key.as_ref();
+    let reference_id = vars.reference_id.as_deref().unwrap_or_default();
+    let rendered = template
+        .replace("{entity_path}", vars.entity_path.trim_start_matches('/'))
+        .replace("{system}", topic_key.map_or("", |key| key.system))
+        .replace("{node}", topic_key.map_or("", |key| key.node))
+        .replace("{topic}", topic_key.map_or("", |key| key.topic))
+        .replace("{version}", topic_key.map_or("", |key| key.version))
+        .replace("{message_type}", vars.message_type)
+        .replace("{reference_id}", reference_id);
+
+    // Empty values must not leave empty path segments behind
+    rendered
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReplaceRule;
+
+    fn vars<'a>(entity_path: &'a str, reference_id: Option<&str>) -> PathVariables<'a> {
+        PathVariables {
+            entity_path,
+            topic_key: TopicKey::parse("robot/camera/front/make87_messages-ImageJPEG/v1"),
+            message_type: "ImageJPEG",
+            reference_id: reference_id.map(str::to_string),
+        }
+    }
+
+    fn rules(
+        template: Option<&str>,
+        replace: &[(&str, &str)],
+        prefix: Option<&str>,
+    ) -> EntityPathRules {
+        EntityPathRules::new(&EntityPathConfig {
+            template: template.map(str::to_string),
+            replace: replace
+                .iter()
+                .map(|(pattern, replacement)| ReplaceRule {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                })
+                .collect(),
+            prefix: prefix.map(str::to_string),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn defaults_to_the_header_entity_path() {
+        let rules = rules(None, &[], None);
+        assert_eq!(rules.apply(&vars("camera/left", None)), "/camera/left");
+        assert_eq!(rules.apply(&vars("/camera/left", None)), "/camera/left");
+    }
+
+    #[test]
+    fn renders_template_variables() {
+        let rules = rules(
+            Some("{system}/{node}/{topic}/{version}/{message_type}"),
+            &[],
+            None,
+        );
+        assert_eq!(
+            rules.apply(&vars("ignored", None)),
+            "/robot/camera/front/v1/ImageJPEG"
+        );
+    }
+
+    #[test]
+    fn empty_values_leave_no_empty_segments() {
+        let rules = rules(Some("/{node}/{reference_id}/{entity_path}/"), &[], None);
+        assert_eq!(rules.apply(&vars("/left", None)), "/camera/left");
+        assert_eq!(rules.apply(&vars("/left", Some("rig"))), "/camera/rig/left");
+    }
+
+    #[test]
+    fn applies_replacements_in_order_before_the_prefix() {
+        let rules = rules(
+            None,
+            &[("^cam_", "camera/"), ("camera/(\\w+)", "cameras/$1")],
+            Some("/fleet/"),
+        );
+        assert_eq!(rules.apply(&vars("cam_left", None)), "/fleet/cameras/left");
+    }
+
+    #[test]
+    fn known_ancestor_stops_at_the_first_unknown_segment() {
+        let topic_key = TopicKey::parse("robot/camera/front/make87_messages-ImageJPEG/v1");
+
+        let header_path = rules(None, &[], None);
+        assert_eq!(
+            header_path.known_ancestor(topic_key, Some("ImageJPEG")),
+            "/"
+        );
+        let prefixed = rules(None, &[], Some("fleet/"));
+        assert_eq!(
+            prefixed.known_ancestor(topic_key, Some("ImageJPEG")),
+            "/fleet"
+        );
+
+        let templated = rules(Some("{node}/{topic}/cam_{entity_path}"), &[], Some("fleet"));
+        assert_eq!(
+            templated.known_ancestor(topic_key, Some("ImageJPEG")),
+            "/fleet/camera/front"
+        );
+        assert_eq!(templated.known_ancestor(None, Some("ImageJPEG")), "/fleet");
+
+        let replaced = rules(Some("{node}/{message_type}"), &[("camera", "cam")], None);
+        assert_eq!(
+            replaced.known_ancestor(topic_key, Some("ImageJPEG")),
+            "/cam/ImageJPEG"
+        );
+        assert_eq!(replaced.known_ancestor(topic_key, None), "/");
+    }
+
+    #[test]
+    fn rejects_invalid_replace_patterns() {
+        let config = EntityPathConfig {
+            replace: vec![ReplaceRule {
+                pattern: "(".to_string(),
+                replacement: String::new(),
+            }],
+            ..Default::default()
+        };
+        assert!(EntityPathRules::new(&config).is_err());
+    }
+}
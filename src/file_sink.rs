@@ -0,0 +1,183 @@
+use crate::config::FileSinkConfig;
+use rerun::log::LogMsg;
+use rerun::sink::{FileSink, LogSink};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const FILE_PREFIX: &str = "recording-";
+
+/// Approximate size of a message, dominated by its Arrow data
+pub fn message_size(msg: &LogMsg) -> u64 {
+    match msg {
+        LogMsg::ArrowMsg(_, arrow_msg) => arrow_msg.batch.get_array_memory_size() as u64,
+        _ => 0,
+    }
+}
+
+struct OpenFile {
+    sink: FileSink,
+    opened_at: Instant,
+    // Bytes sent so far; the file on disk lags behind as the sink writes in the background
+    bytes: u64,
+}
+
+struct RotationState {
+    current: Option<OpenFile>,
+    next_index: u64,
+    // Replayed at the start of every file so each one can be opened on its own
+    store_info: Option<LogMsg>,
+}
+
+/// Writes log messages to local `.rrd` files, starting a new file once the current one
+/// exceeds the configured size or age and deleting the oldest files beyond `max_files`.
+pub struct RotatingFileSink {
+    config: FileSinkConfig,
+    state: Mutex<RotationState>,
+}
+
+impl RotatingFileSink {
+    pub fn new(config: FileSinkConfig) -> std::io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        Ok(Self {
+            config,
+            state: Mutex::new(RotationState {
+                current: None,
+                next_index: 0,
+                store_info: None,
+            }),
+        })
+    }
+
+    fn needs_rotation(&self, file: &OpenFile) -> bool {
+        file.bytes >= self.config.max_file_size_bytes
+            || file.opened_at.elapsed() >= Duration::from_secs_f64(self.config.max_file_duration)
+    }
+
+    fn open_file(&self, state: &mut RotationState) -> Option<OpenFile> {
+        let unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = Path::new(&self.config.directory).join(format!(
+            "{}{:010}-{:06}.rrd",
+            FILE_PREFIX, unix_secs, state.next_index
+        ));
+        state.next_index += 1;
+
+        let sink = match FileSink::new(&path) {
+            Ok(sink) => sink,
+            Err(e) => {
+                log::error!("Failed to open recording file {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        if let Some(store_info) = &state.store_info {
+            sink.send(store_info.clone());
+        }
+        log::info!("Recording to {}", path.display());
+
+        self.enforce_retention();
+        Some(OpenFile {
+            sink,
+            opened_at: Instant::now(),
+            bytes: 0,
+        })
+    }
+
+    /// Delete the oldest recording files beyond `max_files`
+    fn enforce_retention(&self) {
+        let Ok(entries) = fs::read_dir(&self.config.directory) else {
+            return;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|ext| ext == "rrd")
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with(FILE_PREFIX))
+            })
+            .collect();
+        // File names sort chronologically
+        files.sort();
+
+        let excess = files.len().saturating_sub(self.config.max_files);
+        for path in files.into_iter().take(excess) {
+            if let Err(e) = fs::remove_file(&path) {
+                log::error!("Failed to remove recording file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+impl LogSink for RotatingFileSink {
+    fn send(&self, msg: LogMsg) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(msg, LogMsg::SetStoreInfo(_)) {
+            state.store_info = Some(msg.clone());
+        }
+
+        if state
+            .current
+            .as_ref()
+            .is_some_and(|file| self.needs_rotation(file))
+        {
+            // Dropping the file sink finishes the file
+            state.current = None;
+        }
+        if state.current.is_none() {
+            let file = self.open_file(&mut state);
+            state.current = file;
+        }
+
+        if let Some(file) = &mut state.current {
+            file.bytes += message_size(&msg);
+            file.sink.send(msg);
+        }
+    }
+
+    fn flush_blocking(&self) {
+        if let Some(file) = &self.state.lock().unwrap().current {
+            file.sink.flush_blocking();
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Forwards every message to the viewer connection of `transport` and to local files.
+pub struct TeeSink {
+    transport: rerun::RecordingStream,
+    files: Arc<RotatingFileSink>,
+}
+
+impl TeeSink {
+    pub fn new(transport: rerun::RecordingStream, files: Arc<RotatingFileSink>) -> Self {
+        Self { transport, files }
+    }
+
+    pub fn transport(&self) -> &rerun::RecordingStream {
+        &self.transport
+    }
+}
+
+impl LogSink for TeeSink {
+    fn send(&self, msg: LogMsg) {
+        self.files.send(msg.clone());
+        self.transport.record_msg(msg);
+    }
+
+    fn flush_blocking(&self) {
+        self.files.flush_blocking();
+        self.transport.flush_blocking();
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
@@ -0,0 +1,195 @@
+use crate::config::{FilterConfig, PatternList};
+use regex::Regex;
+use std::fmt::Write;
+
+/// Compile a filter pattern. Patterns prefixed with `re:` are regular expressions, all
+/// others are globs where `*` matches within a path segment, `**` across segments and
+/// `?` a single character other than `/`.
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    if let Some(regex) = pattern.strip_prefix("re:") {
+        return Regex::new(regex);
+    }
+
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+struct Rule {
+    name: String,
+    pattern: Regex,
+    dropped: u64,
+}
+
+/// Allow and deny lists for one property of a sample, with a drop counter per rule.
+struct RuleSet {
+    include: Vec<Rule>,
+    exclude: Vec<Rule>,
+    // Samples dropped because no include rule matched
+    not_included: u64,
+    name: &'static str,
+}
+
+impl RuleSet {
+    fn new(name: &'static str, patterns: &PatternList) -> Result<Self, regex::Error> {
+        let compile = |kind: &str, patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Ok(Rule {
+                        name: format!("{}.{} '{}'", name, kind, pattern),
+                        pattern: compile_pattern(pattern)?,
+                        dropped: 0,
+                    })
+                })
+                .collect::<Result<Vec<_>, regex::Error>>()
+        };
+        Ok(Self {
+            include: compile("include", &patterns.include)?,
+            exclude: compile("exclude", &patterns.exclude)?,
+            not_included: 0,
+            name,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns whether `value` passes, counting the drop against the responsible rule
+    fn allows(&mut self, value: &str) -> bool {
+        if !self.include.is_empty() && !self.include.iter().any(|rule| rule.pattern.is_match(value))
+        {
+            self.not_included += 1;
+            return false;
+        }
+        if let Some(rule) = self
+            .exclude
+            .iter_mut()
+            .find(|rule| rule.pattern.is_match(value))
+        {
+            rule.dropped += 1;
+            return false;
+        }
+        true
+    }
+
+    fn drop_counts(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let not_included = (!self.include.is_empty()).then(|| {
+            (
+                format!("{}.include (no match)", self.name),
+                self.not_included,
+            )
+        });
+        not_included.into_iter().chain(
+            self.exclude
+                .iter()
+                .map(|rule| (rule.name.clone(), rule.dropped)),
+        )
+    }
+}
+
+/// Include/exclude filters on message types and entity paths of a subscriber.
+pub struct SampleFilter {
+    message_types: RuleSet,
+    entity_paths: RuleSet,
+}
+
+impl SampleFilter {
+    pub fn new(config: &FilterConfig) -> Result<Self, regex::Error> {
+        Ok(Self {
+            message_types: RuleSet::new("message_types", &config.message_types)?,
+            entity_paths: RuleSet::new("entity_paths", &config.entity_paths)?,
+        })
+    }
+
+    pub fn allows_message_type(&mut self, message_type: &str) -> bool {
+        self.message_types.allows(message_type)
+    }
+
+    /// Entity path filtering needs the header, so callers can skip decoding it without rules
+    pub fn filters_entity_paths(&self) -> bool {
+        !self.entity_paths.is_empty()
+    }
+
+    pub fn allows_entity_path(&mut self, entity_path: &str) -> bool {
+        self.entity_paths.allows(entity_path)
+    }
+
+    /// Number of samples dropped per rule, for rules that dropped any
+    pub fn drop_counts(&self) -> Vec<(String, u64)> {
+        self.message_types
+            .drop_counts()
+            .chain(self.entity_paths.drop_counts())
+            .filter(|(_, dropped)| *dropped > 0)
+            .collect()
+    }
+
+    pub fn summary(&self) -> Option<String> {
+        let counts = self.drop_counts();
+        if counts.is_empty() {
+            return None;
+        }
+        let mut summary = String::new();
+        for (rule, dropped) in counts {
+            let _ = write!(summary, "{}: {}, ", rule, dropped);
+        }
+        Some(summary.trim_end_matches(", ").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_star_stays_within_a_segment() {
+        let pattern = compile_pattern("/camera/*").unwrap();
+        assert!(pattern.is_match("/camera/front"));
+        assert!(!pattern.is_match("/camera/front/depth"));
+    }
+
+    #[test]
+    fn double_star_crosses_segments() {
+        let pattern = compile_pattern("/camera/**").unwrap();
+        assert!(pattern.is_match("/camera/front"));
+        assert!(pattern.is_match("/camera/front/depth"));
+        assert!(!pattern.is_match("/lidar/top"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character_but_not_a_separator() {
+        let pattern = compile_pattern("/camera?").unwrap();
+        assert!(pattern.is_match("/camera1"));
+        assert!(!pattern.is_match("/camera"));
+        assert!(!pattern.is_match("/camera12"));
+        assert!(!compile_pattern("/a?b").unwrap().is_match("/a/b"));
+    }
+
+    #[test]
+    fn escapes_regex_characters_in_globs() {
+        let pattern = compile_pattern("Image.JPEG").unwrap();
+        assert!(pattern.is_match("Image.JPEG"));
+        assert!(!pattern.is_match("ImageXJPEG"));
+    }
+
+    #[test]
+    fn re_prefix_compiles_a_regular_expression() {
+        let pattern = compile_pattern("re:^Image(JPEG|PNG)$").unwrap();
+        assert!(pattern.is_match("ImagePNG"));
+        assert!(!pattern.is_match("ImageRaw"));
+        assert!(compile_pattern("re:(").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::{HeadlessConfig, HeadlessSink};
+use crate::connection::recording_builder;
+use crate::file_sink::{message_size, RotatingFileSink};
+use rerun::log::LogMsg;
+use rerun::sink::{FileSink, LogSink};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::signal::unix::{signal, SignalKind};
+
+#[derive(Default)]
+struct MemoryState {
+    // Written at the start of every dump so each one can be opened on its own
+    store_info: Option<LogMsg>,
+    // Messages since the last dump and their approximate sizes, oldest first
+    messages: VecDeque<(LogMsg, u64)>,
+    bytes: u64,
+}
+
+/// Keeps logged messages in memory, dropping the oldest ones beyond `max_bytes`.
+struct MemorySink {
+    state: Arc<Mutex<MemoryState>>,
+    max_bytes: u64,
+}
+
+impl LogSink for MemorySink {
+    fn send(&self, msg: LogMsg) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(msg, LogMsg::SetStoreInfo(_)) {
+            state.store_info = Some(msg);
+            return;
+        }
+
+        let size = message_size(&msg);
+        state.messages.push_back((msg, size));
+        state.bytes += size;
+        let mut dropped = 0;
+        while state.bytes > self.max_bytes {
+            let Some((_, size)) = state.messages.pop_front() else {
+                break;
+            };
+            state.bytes -= size;
+            dropped += 1;
+        }
+        if dropped > 0 {
+            log::warn!(
+                "In-memory recording full, dropped the {} oldest messages",
+                dropped
+            );
+        }
+    }
+
+    fn flush_blocking(&self) {}
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Recording kept in memory until it is dumped to disk.
+#[derive(Clone)]
+pub struct MemoryRecording {
+    state: Arc<Mutex<MemoryState>>,
+    directory: String,
+}
+
+impl MemoryRecording {
+    /// Write everything recorded since the last dump to a new `.rrd` file
+    pub fn dump(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (store_info, messages) = {
+            let mut state = self.state.lock().unwrap();
+            state.bytes = 0;
+            (
+                state.store_info.clone(),
+                std::mem::take(&mut state.messages),
+            )
+        };
+        let unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = Path::new(&self.directory).join(format!("memory-{:010}.rrd", unix_secs));
+
+        let sink = FileSink::new(&path)?;
+        for msg in store_info
+            .into_iter()
+            .chain(messages.into_iter().map(|(msg, _)| msg))
+        {
+            sink.send(msg);
+        }
+        // Dropping the file sink finishes the file
+        drop(sink);
+        log::info!("Dumped in-memory recording to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Build a recording stream that records locally without any viewer connection.
+///
+/// For the memory sink the returned handle must be dumped at shutdown, anything not
+/// dumped is lost.
+pub fn recording_stream(
+    config: &HeadlessConfig,
+    system_id: &str,
+) -> Result<(rerun::RecordingStream, Option<MemoryRecording>), Box<dyn Error + Send + Sync>> {
+    let builder = recording_builder(system_id);
+    match config.sink {
+        HeadlessSink::File => {
+            let rec = builder.buffered()?;
+            rec.set_sink(Box::new(RotatingFileSink::new(config.file_sink.clone())?));
+            log::info!("Headless recording to {}", config.file_sink.directory);
+            Ok((rec, None))
+        }
+        HeadlessSink::Memory => {
+            let rec = builder.buffered()?;
+            std::fs::create_dir_all(&config.file_sink.directory)?;
+            let memory = MemoryRecording {
+                state: Arc::new(Mutex::new(MemoryState::default())),
+                directory: config.file_sink.directory.clone(),
+            };
+            rec.set_sink(Box::new(MemorySink {
+                state: memory.state.clone(),
+                max_bytes: config.max_memory_bytes,
+            }));
+            tokio::spawn(dump_on_signal(memory.clone()));
+            log::info!(
+                "Headless recording in memory, send SIGUSR1 to dump it to {}",
+                config.file_sink.directory
+            );
+            Ok((rec, Some(memory)))
+        }
+    }
+}
+
+async fn dump_on_signal(memory: MemoryRecording) {
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        if let Err(e) = memory.dump() {
+            log::error!("Failed to dump in-memory recording: {}", e);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClassEntry {
+    Name(String),
+    Full {
+        name: String,
+        #[serde(default)]
+        color: Option<Vec<u8>>,
+    },
+}
+
+struct ClassLabel {
+    name: String,
+    color: Option<rerun::datatypes::Rgba32>,
+}
+
+/// Class names and colors by class id, loaded from a JSON or YAML file mapping each id to
+/// either a name or `{name, color: [r, g, b(, a)]}`.
+pub struct LabelMap {
+    classes: BTreeMap<u16, ClassLabel>,
+}
+
+impl LabelMap {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read label map {}: {}", path.display(), e))?;
+        Self::parse(&content)
+            .map_err(|e| format!("Invalid label map {}: {}", path.display(), e).into())
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        // YAML is a superset of JSON, so one parser covers both. Keys are read as strings
+        // since JSON object keys are always quoted.
+        let entries: BTreeMap<String, ClassEntry> =
+            serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+
+        let classes = entries
+            .into_iter()
+            .map(|(id, entry)| {
+                let id: u16 = id
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid class id '{}', expected 0 to 65535", id))?;
+                let (name, color) = match entry {
+                    ClassEntry::Name(name) => (name, None),
+                    ClassEntry::Full { name, color } => (name, color),
+                };
+                let color = match color.as_deref() {
+                    None => None,
+                    Some(&[r, g, b]) => Some(rerun::datatypes::Rgba32::from_rgb(r, g, b)),
+                    Some(&[r, g, b, a]) => {
+                        Some(rerun::datatypes::Rgba32::from_unmultiplied_rgba(r, g, b, a))
+                    }
+                    Some(_) => {
+                        return Err(format!(
+                            "Invalid color of class {}, expected [r, g, b(, a)]",
+                            id
+                        ))
+                    }
+                };
+                Ok((id, ClassLabel { name, color }))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { classes })
+    }
+
+    pub fn name(&self, class_id: u16) -> Option<&str> {
+        self.classes.get(&class_id).map(|class| class.name.as_str())
+    }
+
+    /// Ids of all classes with the given name
+    pub fn ids<'a>(&'a self, name: &'a str) -> impl Iterator<Item = u16> + 'a {
+        self.classes
+            .iter()
+            .filter(move |(_, class)| class.name == name)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn color(&self, class_id: u16) -> Option<rerun::Color> {
+        self.classes
+            .get(&class_id)
+            .and_then(|class| class.color)
+            .map(rerun::Color::from)
+    }
+
+    fn annotation_context(&self) -> rerun::AnnotationContext {
+        rerun::AnnotationContext::new(self.classes.iter().map(|(id, class)| {
+            rerun::datatypes::ClassDescription::from(rerun::datatypes::AnnotationInfo {
+                id: *id,
+                label: Some(class.name.as_str().into()),
+                color: class.color,
+            })
+        }))
+    }
+}
+
+/// Annotation contexts logged as static data per entity path. They are kept so they can be
+/// logged again whenever the shipper reconnects to a viewer that may have lost them.
+#[derive(Default)]
+pub struct Annotations {
+    logged: Mutex<HashMap<String, Arc<LabelMap>>>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Log the annotation context of `labels` at `entity_path` unless it already is
+    pub fn register(
+        &self,
+        rec: &rerun::RecordingStream,
+        entity_path: &str,
+        labels: &Arc<LabelMap>,
+    ) -> Result<(), rerun::RecordingStreamError> {
+        let mut logged = self.logged.lock().unwrap();
+        if logged
+            .get(entity_path)
+            .is_some_and(|logged| Arc::ptr_eq(logged, labels))
+        {
+            return Ok(());
+        }
+        rec.log_static(entity_path, &labels.annotation_context())?;
+        logged.insert(entity_path.to_string(), labels.clone());
+        Ok(())
+    }
+
+    /// Log all annotation contexts again, e.g. to a new connection
+    pub fn relog(&self, rec: &rerun::RecordingStream) {
+        for (entity_path, labels) in self.logged.lock().unwrap().iter() {
+            if let Err(e) = rec.log_static(entity_path.as_str(), &labels.annotation_context()) {
+                log::error!("Failed to log annotation context at {}: {}", entity_path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file_name: &str, content: &str) -> Result<LabelMap, String> {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), file_name));
+        std::fs::write(&path, content).unwrap();
+        let label_map = LabelMap::load(&path).map_err(|e| e.to_string());
+        std::fs::remove_file(&path).unwrap();
+        label_map
+    }
+
+    #[test]
+    fn loads_json() {
+        let label_map = load(
+            "labels.json",
+            r#"{"0": "person", "2": {"name": "car", "color": [255, 0, 0]}}"#,
+        )
+        .unwrap();
+        assert_eq!(label_map.name(0), Some("person"));
+        assert_eq!(label_map.name(1), None);
+        assert_eq!(label_map.name(2), Some("car"));
+        assert_eq!(label_map.color(0), None);
+        assert_eq!(label_map.color(2), Some(rerun::Color::from_rgb(255, 0, 0)));
+    }
+
+    #[test]
+    fn loads_yaml() {
+        let label_map = load(
+            "labels.yaml",
+            "0: person\n2:\n  name: car\n  color: [255, 0, 0, 128]\n",
+        )
+        .unwrap();
+        assert_eq!(label_map.name(0), Some("person"));
+        assert_eq!(label_map.name(2), Some("car"));
+        assert_eq!(label_map.ids("car").collect::<Vec<_>>(), vec![2]);
+        assert_eq!(label_map.ids("bus").count(), 0);
+        assert_eq!(
+            label_map.color(2),
+            Some(rerun::Color::from_unmultiplied_rgba(255, 0, 0, 128))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ids_and_colors() {
+        assert!(load("negative.yaml", "-1: person\n").is_err());
+        assert!(load("large.json", r#"{"65536": "person"}"#).is_err());
+        assert!(load("name.json", r#"{"person": "person"}"#).is_err());
+        assert!(load("color.yaml", "0: {name: person, color: [1, 2]}\n").is_err());
+    }
+}
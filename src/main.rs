@@ -1,32 +1,184 @@
 use make87::interfaces::rerun::RerunGRpcInterface;
 use make87::interfaces::zenoh::{ConfiguredSubscriber, ZenohInterface};
 use std::error::Error;
-use std::sync::mpsc;
+use std::future::Future;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
 
+mod config;
+mod connection;
+mod discovery;
+mod entity_path;
+mod file_sink;
+mod filter;
+mod headless;
+mod label_map;
 mod message_handlers;
-use message_handlers::MessageTypeRegistry;
+mod rate_limit;
+mod server;
+mod spool;
+mod telemetry;
+mod topic_key;
+mod workers;
+use config::{RerunClientConfig, ShipperConfig, SubscriberConfig};
+use connection::{Connector, SharedRecordingStream, RERUN_CLIENT_NAME};
+use file_sink::RotatingFileSink;
+use label_map::Annotations;
+use message_handlers::{
+    normalize_message_type, ImageSizes, MessageDispatcher, MessageTypeRegistry, ReceivedSample,
+};
+use rate_limit::RateLimiter;
+use spool::Spool;
+use telemetry::Telemetry;
+use workers::DecodePool;
 
-/// Check if the gRPC connection is still active
-fn check_grpc_connection(rec: &rerun::RecordingStream) -> bool {
-    let (tx, rx) = mpsc::channel();
+/// State shared by the receive tasks of all subscribers and discovered topics.
+///
+/// The decode workers exit once every clone holding them is dropped.
+#[derive(Clone)]
+pub struct ShipperContext {
+    pub registry: Arc<MessageTypeRegistry>,
+    pub rec: Arc<SharedRecordingStream>,
+    pub workers: Option<DecodePool>,
+    pub telemetry: Arc<Telemetry>,
+    pub annotations: Arc<Annotations>,
+    pub image_sizes: Arc<ImageSizes>,
+}
 
-    rec.inspect_sink(move |sink| {
-        if let Some(grpc_sink) = sink.as_any().downcast_ref::<rerun::sink::GrpcSink>() {
-            let _ = tx.send(grpc_sink.status());
+/// Decode and log a sample, on the decode workers if there are any
+async fn handle_sample(
+    dispatcher: &mut MessageDispatcher,
+    sample: ReceivedSample,
+    ctx: &ShipperContext,
+) {
+    let key_expr = sample.sample.key_expr().clone();
+    let job = match dispatcher.prepare(sample) {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => {
+            log::error!("Error handling message on {}: {}", key_expr, e);
+            return;
         }
-    });
-
-    // Check with a short timeout
-    if let Ok(status) = rx.recv_timeout(Duration::from_millis(100)) {
-        match status {
-            rerun::sink::GrpcSinkConnectionState::Connected => true,
-            rerun::sink::GrpcSinkConnectionState::Connecting => true, // Still trying
-            rerun::sink::GrpcSinkConnectionState::Disconnected(_) => false,
+    };
+
+    match &ctx.workers {
+        Some(workers) => workers.submit(job).await,
+        None => {
+            if let Err(e) = job.run(&ctx.rec.get()) {
+                log::error!("Error handling message on {}: {}", key_expr, e);
+            }
+        }
+    }
+}
+
+fn subscriber_key_expr(configured_subscriber: &ConfiguredSubscriber) -> String {
+    match configured_subscriber {
+        ConfiguredSubscriber::Fifo(sub) => sub.key_expr().to_string(),
+        ConfiguredSubscriber::Ring(sub) => sub.key_expr().to_string(),
+    }
+}
+
+/// Receive loop for a single configured subscriber.
+async fn run_subscriber(
+    name: String,
+    configured_subscriber: ConfiguredSubscriber,
+    dispatcher: MessageDispatcher,
+    limiter: RateLimiter,
+    ctx: ShipperContext,
+) {
+    match configured_subscriber {
+        ConfiguredSubscriber::Fifo(sub) => {
+            let sub = &sub;
+            receive_samples(
+                move || async move { sub.recv_async().await.ok() },
+                dispatcher,
+                limiter,
+                &ctx,
+            )
+            .await
+        }
+        ConfiguredSubscriber::Ring(sub) => {
+            let sub = &sub;
+            receive_samples(
+                move || async move { sub.recv_async().await.ok() },
+                dispatcher,
+                limiter,
+                &ctx,
+            )
+            .await
+        }
+    }
+
+    log::warn!("Subscriber '{}' closed", name);
+}
+
+/// Hands received samples to the dispatcher as the rate limiter lets them through, shipping
+/// held back samples once their slot comes. Returns when `recv` yields `None`.
+async fn receive_samples<F, Fut>(
+    mut recv: F,
+    mut dispatcher: MessageDispatcher,
+    mut limiter: RateLimiter,
+    ctx: &ShipperContext,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<zenoh::sample::Sample>>,
+{
+    loop {
+        let deadline = limiter.next_deadline();
+        tokio::select! {
+            sample = recv() => {
+                let Some(sample) = sample else {
+                    break;
+                };
+                if let Some(sample) = limiter.offer(ReceivedSample::now(sample)) {
+                    handle_sample(&mut dispatcher, sample, ctx).await;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()),
+                if deadline.is_some() => {
+                for sample in limiter.take_due() {
+                    handle_sample(&mut dispatcher, sample, ctx).await;
+                }
+            }
         }
-    } else {
-        // Timeout or no status - assume disconnected
-        false
+    }
+}
+
+/// Resolves once SIGINT or SIGTERM is received, returning the signal name
+async fn shutdown_signal() -> &'static str {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            log::error!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return "SIGINT";
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+/// Flush everything logged so far within `timeout` and close all sinks, including files
+async fn flush_and_close(
+    rec: rerun::RecordingStream,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let flushing = rec.clone();
+    let flushed = tokio::time::timeout(
+        timeout,
+        tokio::task::spawn_blocking(move || flushing.flush_blocking()),
+    )
+    .await;
+    // Swapping out the sinks finishes any files they write
+    tokio::task::spawn_blocking(move || rec.disconnect()).await?;
+    match flushed {
+        Ok(joined) => joined.map_err(Into::into),
+        Err(_) => Err(format!("Failed to flush recording stream within {:?}", timeout).into()),
     }
 }
 
@@ -35,89 +187,192 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     env_logger::init();
 
     let application_config = make87::config::load_config_from_default_env()?;
+    let shipper_config = ShipperConfig::from_value(&application_config.config)?;
+    let rerun_client_config = application_config
+        .interfaces
+        .get("rerun-grpc")
+        .and_then(|interface| interface.clients.get(RERUN_CLIENT_NAME))
+        .map(|client| RerunClientConfig::from_map(&client.config.config))
+        .transpose()?
+        .unwrap_or_default();
 
     let zenoh_interface = ZenohInterface::new(application_config.clone(), "zenoh");
     let session = zenoh_interface.get_session().await?;
 
-    let rerun_grpc_interface = RerunGRpcInterface::new(application_config.clone(), "rerun-grpc");
-    let mut rec = rerun_grpc_interface.get_client_recording_stream("rerun-grpc-client")?;
-    let mut last_connection_check = Instant::now();
-    const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+    let telemetry = Arc::new(Telemetry::new());
+    let annotations = Arc::new(Annotations::new());
+    let image_sizes = Arc::new(ImageSizes::new());
+    let system_id = application_config.application_info.system_id.as_str();
+    let mut supervisor = None;
+    let mut memory_recording = None;
+    let rec = if shipper_config.headless.enabled {
+        // Flight recorder mode, the rerun-grpc client is not used at all
+        let (rec, memory) = headless::recording_stream(&shipper_config.headless, system_id)?;
+        memory_recording = memory;
+        Arc::new(SharedRecordingStream::new(rec))
+    } else if shipper_config.server.enabled {
+        // Viewers connect to the shipper instead of the other way around
+        Arc::new(SharedRecordingStream::new(server::recording_stream(
+            &shipper_config.server,
+            system_id,
+        )?))
+    } else {
+        let files = if rerun_client_config.file_sink.enabled {
+            Some(Arc::new(RotatingFileSink::new(
+                rerun_client_config.file_sink.clone(),
+            )?))
+        } else {
+            None
+        };
+
+        let rerun_grpc_interface =
+            RerunGRpcInterface::new(application_config.clone(), "rerun-grpc");
+        let connector = Connector::new(rerun_grpc_interface, files);
+        let rec = Arc::new(SharedRecordingStream::new(connector.connect()?));
+        let spool = if shipper_config.spool.enabled {
+            Some(Spool::new(shipper_config.spool.clone())?)
+        } else {
+            None
+        };
+        supervisor = Some(tokio::spawn(connection::run_connection_supervisor(
+            connector,
+            rec.clone(),
+            shipper_config.connection.clone(),
+            spool,
+            telemetry.clone(),
+            annotations.clone(),
+        )));
+        rec
+    };
+    let registry =
+        Arc::new(MessageTypeRegistry::new().with_fallback(shipper_config.fallback.clone()));
+    let (decode_pool, decode_workers) = if shipper_config.workers.count > 0 {
+        let (pool, workers) = DecodePool::spawn(&shipper_config.workers, rec.clone());
+        (Some(pool), workers)
+    } else {
+        (None, Vec::new())
+    };
+    let ctx = ShipperContext {
+        registry,
+        rec: rec.clone(),
+        workers: decode_pool,
+        telemetry: telemetry.clone(),
+        annotations,
+        image_sizes,
+    };
 
-    let configured_subscriber = zenoh_interface
-        .get_subscriber(&session, "any_message")
-        .await?;
+    // Every subscriber bound on the zenoh interface gets its own handler and receive task
+    let mut subscriber_names: Vec<String> = application_config
+        .interfaces
+        .get("zenoh")
+        .map(|interface| interface.subscribers.keys().cloned().collect())
+        .unwrap_or_default();
+    subscriber_names.sort();
+    if subscriber_names.is_empty() && !shipper_config.ship_all.enabled {
+        return Err("No subscribers configured on the zenoh interface".into());
+    }
 
-    match configured_subscriber {
-        ConfiguredSubscriber::Fifo(sub) => {
-            // Create registry and determine handler from topic_key
-            let registry = MessageTypeRegistry::new();
-            let handler = registry
-                .create_handler_from_topic_key(sub.key_expr())
-                .ok_or_else(|| format!("Unknown message type for topic: {}", sub.key_expr()))?;
-
-            while let Ok(sample) = sub.recv_async().await {
-                // Periodically check connection status
-                if last_connection_check.elapsed() >= CONNECTION_CHECK_INTERVAL {
-                    if !check_grpc_connection(&rec) {
-                        log::warn!("gRPC connection lost, attempting to reconnect...");
-                        match rerun_grpc_interface.get_client_recording_stream("rerun-grpc-client")
-                        {
-                            Ok(new_rec) => {
-                                // Gracefully disconnect the old connection
-                                rec.disconnect();
-                                rec = new_rec;
-                                log::info!("Successfully reconnected to gRPC server");
-                            }
-                            Err(e) => {
-                                log::error!("Failed to reconnect to gRPC server: {}", e);
-                                // Continue with old connection, might recover
-                            }
-                        }
-                    }
-                    last_connection_check = Instant::now();
-                }
+    let mut tasks: JoinSet<Result<(), Box<dyn Error + Send + Sync>>> = JoinSet::new();
+    let mut configured_key_exprs = Vec::with_capacity(subscriber_names.len());
+    for name in subscriber_names {
+        let subscriber_config = application_config
+            .interfaces
+            .get("zenoh")
+            .and_then(|interface| interface.subscribers.get(&name))
+            .map(|subscriber| SubscriberConfig::from_map(&subscriber.config.config))
+            .transpose()?
+            .unwrap_or_default();
+        let configured_subscriber = zenoh_interface.get_subscriber(&session, &name).await?;
 
-                if let Err(e) = handler.handle_message(&sample, &rec) {
-                    log::error!("Error handling message: {}", e);
-                }
+        // Concrete topic keys must name a known message type; wildcard keys are
+        // resolved per sample by the dispatcher
+        let key_expr = subscriber_key_expr(&configured_subscriber);
+        let message_type = match &subscriber_config.message_type {
+            Some(message_type) => Some(normalize_message_type(message_type)),
+            None if key_expr.contains('*') => None,
+            None => ctx
+                .registry
+                .extract_message_type_from_topic_key(&key_expr)
+                .map(str::to_string),
+        };
+        if let Some(message_type) = &message_type {
+            if !ctx.registry.can_handle(message_type) {
+                return Err(format!(
+                    "Unknown message type {} for topic: {}",
+                    message_type, key_expr
+                )
+                .into());
             }
         }
-        ConfiguredSubscriber::Ring(sub) => {
-            // Create registry and determine handler from topic_key
-            let registry = MessageTypeRegistry::new();
-            let handler = registry
-                .create_handler_from_topic_key(sub.key_expr())
-                .ok_or_else(|| format!("Unknown message type for topic: {}", sub.key_expr()))?;
-
-            while let Ok(sample) = sub.recv_async().await {
-                // Periodically check connection status
-                if last_connection_check.elapsed() >= CONNECTION_CHECK_INTERVAL {
-                    if !check_grpc_connection(&rec) {
-                        log::warn!("gRPC connection lost, attempting to reconnect...");
-                        match rerun_grpc_interface.get_client_recording_stream("rerun-grpc-client")
-                        {
-                            Ok(new_rec) => {
-                                // Gracefully disconnect the old connection
-                                rec.disconnect();
-                                rec = new_rec;
-                                log::info!("Successfully reconnected to gRPC server");
-                            }
-                            Err(e) => {
-                                log::error!("Failed to reconnect to gRPC server: {}", e);
-                                // Continue with old connection, might recover
-                            }
-                        }
-                    }
-                    last_connection_check = Instant::now();
-                }
+        log::info!("Subscriber '{}' shipping {}", name, key_expr);
 
-                if let Err(e) = handler.handle_message(&sample, &rec) {
-                    log::error!("Error handling message: {}", e);
-                }
+        let limiter = RateLimiter::new(&subscriber_config);
+        let dispatcher = MessageDispatcher::new(&ctx, subscriber_config)?;
+        dispatcher.log_static(&key_expr, &rec.get())?;
+        configured_key_exprs.push(key_expr);
+        let ctx = ctx.clone();
+        tasks.spawn(async move {
+            run_subscriber(name, configured_subscriber, dispatcher, limiter, ctx).await;
+            Ok(())
+        });
+    }
+
+    if shipper_config.ship_all.enabled {
+        tasks.spawn(discovery::run_discovery(
+            session.clone(),
+            shipper_config.ship_all.clone(),
+            configured_key_exprs,
+            ctx.clone(),
+        ));
+    }
+
+    if shipper_config.telemetry.enabled {
+        tasks.spawn(telemetry::run_telemetry(
+            telemetry.clone(),
+            shipper_config.telemetry.clone(),
+            rec.clone(),
+            ctx.workers.clone(),
+        ));
+    }
+    // Workers exit once the receive tasks drop their handles
+    drop(ctx);
+
+    let result = tokio::select! {
+        signal = shutdown_signal() => {
+            log::info!("Received {}, shutting down", signal);
+            Ok(())
+        }
+        result = async {
+            while let Some(joined) = tasks.join_next().await {
+                joined??;
             }
+            Ok::<_, Box<dyn Error + Send + Sync>>(())
+        } => result,
+    };
+
+    // Stop receiving first so nothing is logged while flushing, and keep the supervisor
+    // from swapping the stream underneath us
+    tasks.shutdown().await;
+    if let Some(supervisor) = supervisor {
+        supervisor.abort();
+        let _ = supervisor.await;
+    }
+
+    let flush_timeout = Duration::from_secs_f64(rerun_client_config.flush_timeout);
+    let drained = tokio::time::timeout(flush_timeout, async {
+        for worker in decode_workers {
+            let _ = worker.await;
         }
+    })
+    .await;
+    if drained.is_err() {
+        log::warn!("Decode workers did not finish their queues in time");
+    }
+    let flushed = flush_and_close(rec.get(), flush_timeout).await;
+    if let Some(memory) = memory_recording {
+        memory.dump()?;
     }
 
-    Ok(())
+    log::info!("Shutdown complete");
+    result.and(flushed)
 }
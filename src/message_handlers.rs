@@ -1,3 +1,13 @@
+use crate::config::{
+    BoxCoordinates, ClassSelector, DetectionConfig, FallbackConfig, SubscriberConfig, TimeSource,
+    TimelineConfig,
+};
+use crate::entity_path::{EntityPathRules, PathVariables};
+use crate::filter::SampleFilter;
+use crate::label_map::{Annotations, LabelMap};
+use crate::telemetry::Telemetry;
+use crate::topic_key::TopicKey;
+use crate::ShipperContext;
 use make87::encodings::{Encoder, ProtobufEncoder};
 use make87_messages::core::Header;
 use make87_messages::detection::r#box::Boxes2DAxisAligned;
@@ -7,10 +17,11 @@ use make87_messages::image::uncompressed::{
     image_raw_any, ImageNv12, ImageRawAny, ImageRgb888, ImageRgba8888, ImageYuv420,
 };
 use make87_messages::text::PlainText;
-use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 fn timestamp_to_secs_f64(ts: &Timestamp) -> f64 {
     ts.seconds as f64 + (ts.nanos as f64 / 1_000_000_000.0)
@@ -23,38 +34,137 @@ fn get_current_timestamp_secs() -> f64 {
         .as_secs_f64()
 }
 
-fn ensure_leading_slash(entity_path: String) -> String {
-    if entity_path.starts_with('/') {
-        entity_path
-    } else {
-        format!("/{}", entity_path)
-    }
+// Entity path of a message after applying the subscriber's rewrite rules
+fn resolve_entity_path(
+    header: &Option<Header>,
+    key_expr: &str,
+    message_type: &str,
+    entity_paths: &EntityPathRules,
+) -> String {
+    let topic_key = TopicKey::parse(key_expr);
+    // Messages without an entity path of their own default to one derived from the topic key
+    let default_entity_path = topic_key
+        .map(|topic_key| topic_key.entity_path())
+        .unwrap_or_else(|| "/".to_string());
+    let entity_path = match header {
+        Some(header) if !header.entity_path.is_empty() => &header.entity_path,
+        _ => &default_entity_path,
+    };
+
+    entity_paths.apply(&PathVariables {
+        entity_path,
+        topic_key,
+        message_type,
+        reference_id: header
+            .as_ref()
+            .map(|header| header.reference_id.to_string()),
+    })
 }
 
 fn process_header_and_set_time(
     header: &Option<Header>,
+    sample: &zenoh::sample::Sample,
+    ctx: &HandlerContext,
     rec: &rerun::RecordingStream,
-) -> (String, f64) {
-    let (entity_path, header_time) = match header {
-        Some(header) => {
-            let time = header
-                .timestamp
-                .map(|ts| timestamp_to_secs_f64(&ts))
-                .unwrap_or_else(get_current_timestamp_secs);
-            (ensure_leading_slash(header.entity_path.clone()), time)
-        }
-        None => ("/".to_string(), get_current_timestamp_secs()),
-    };
+) -> Result<String, Box<dyn Error>> {
+    let header_timestamp = header
+        .as_ref()
+        .and_then(|header| header.timestamp)
+        .map(|ts| timestamp_to_secs_f64(&ts));
+    let (time_source, header_time) = ctx
+        .timelines
+        .time_sources
+        .iter()
+        .find_map(|source| {
+            let time = match source {
+                TimeSource::Header => header_timestamp,
+                TimeSource::Zenoh => zenoh_timestamp_secs(sample),
+                TimeSource::Receive => Some(ctx.received_at),
+            };
+            time.map(|time| (*source, time))
+        })
+        .unwrap_or((TimeSource::Receive, ctx.received_at));
+    ctx.telemetry
+        .record_time_source(sample.key_expr().as_str(), time_source);
+    let entity_path = resolve_entity_path(
+        header,
+        sample.key_expr().as_str(),
+        ctx.message_type,
+        ctx.entity_paths,
+    );
+
+    set_timelines(rec, sample, ctx, header_time);
+
+    // Latency is only meaningful against a timestamp set by the publisher
+    if let (true, Some(header_timestamp)) = (ctx.log_latency, header_timestamp) {
+        rec.log(
+            child_entity_path(&entity_path, "latency_ms"),
+            &rerun::Scalars::single((ctx.received_at - header_timestamp) * 1000.0),
+        )?;
+    }
+    Ok(entity_path)
+}
+
+fn zenoh_timestamp_secs(sample: &zenoh::sample::Sample) -> Option<f64> {
+    sample.timestamp().map(|timestamp| {
+        timestamp
+            .get_time()
+            .to_system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    })
+}
+
+// Time is kept per thread, so everything is reset first to not leak timelines between
+// subscribers sharing a worker
+fn set_timelines(
+    rec: &rerun::RecordingStream,
+    sample: &zenoh::sample::Sample,
+    ctx: &HandlerContext,
+    header_time: f64,
+) {
+    rec.reset_time();
+    if ctx.timelines.header_time {
+        rec.set_timestamp_secs_since_epoch("header_time", header_time);
+    }
+    if ctx.timelines.receive_time {
+        rec.set_timestamp_secs_since_epoch("receive_time", ctx.received_at);
+    }
+    if ctx.timelines.zenoh_time {
+        if let Some(zenoh_time) = zenoh_timestamp_secs(sample) {
+            rec.set_timestamp_secs_since_epoch("zenoh_time", zenoh_time);
+        }
+    }
+    if ctx.timelines.sequence {
+        rec.set_time_sequence("sequence", ctx.sequence as i64);
+    }
+}
 
-    rec.set_timestamp_secs_since_epoch("header_time", header_time);
-    (entity_path, header_time)
+/// Per-sample context handed to message handlers by the dispatcher
+pub struct HandlerContext<'a> {
+    pub message_type: &'a str,
+    pub entity_paths: &'a EntityPathRules,
+    pub timelines: &'a TimelineConfig,
+    pub log_latency: bool,
+    /// Wall-clock time the sample was received at, in seconds since the epoch
+    pub received_at: f64,
+    /// Number of samples received on the topic before this one
+    pub sequence: u64,
+    pub telemetry: &'a Telemetry,
+    /// Class names and colors configured for the topic
+    pub label_map: Option<&'a Arc<LabelMap>>,
+    pub detections: &'a DetectionConfig,
+    pub image_sizes: &'a ImageSizes,
+    pub annotations: &'a Annotations,
 }
 
-pub trait MessageHandler {
+pub trait MessageHandler: Send + Sync {
     fn handle_message(
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>>;
 }
 
@@ -75,9 +185,10 @@ impl MessageHandler for TextPlainTextHandler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         rec.log(entity_path, &rerun::TextDocument::new(message_decoded.body)).map_err(|e| Box::new(e) as Box<dyn Error>)
     }
@@ -100,18 +211,14 @@ impl MessageHandler for ImageCompressedJpegHandler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
 
-        // Print timestamp from header to check camera timing
-        if let Some(header) = &message_decoded.header {
-            if let Some(_timestamp) = &header.timestamp {
-            } else {
-            }
-        } else {
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
+        if let Some(size) = jpeg_dimensions(&message_decoded.data) {
+            ctx.image_sizes.record(&entity_path, size);
         }
-
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
         rec.log(
             entity_path,
             &rerun::EncodedImage::new(message_decoded.data)
@@ -120,6 +227,70 @@ impl MessageHandler for ImageCompressedJpegHandler {
     }
 }
 
+/// Dimensions of the most recent image logged per entity path, for scaling normalized
+/// detections drawn on top of them.
+#[derive(Default)]
+pub struct ImageSizes {
+    sizes: Mutex<HashMap<String, [u32; 2]>>,
+    // Entity paths already reported as missing while boxes wait for their first image
+    missing: Mutex<HashSet<String>>,
+}
+
+impl ImageSizes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(entity_path: &str) -> String {
+        format!("/{}", entity_path.trim_matches('/'))
+    }
+
+    fn record(&self, entity_path: &str, size: [u32; 2]) {
+        self.sizes
+            .lock()
+            .unwrap()
+            .insert(Self::key(entity_path), size);
+    }
+
+    fn get(&self, entity_path: &str) -> Option<[u32; 2]> {
+        self.sizes
+            .lock()
+            .unwrap()
+            .get(&Self::key(entity_path))
+            .copied()
+    }
+
+    /// Warn once per entity path that normalized boxes are skipped until an image arrives
+    fn report_missing(&self, entity_path: &str) {
+        if self.missing.lock().unwrap().insert(Self::key(entity_path)) {
+            log::warn!(
+                "Skipping normalized boxes until an image is logged at {}",
+                entity_path
+            );
+        }
+    }
+}
+
+// Width and height from the start of frame segment of a JPEG, without decoding it
+fn jpeg_dimensions(data: &[u8]) -> Option<[u32; 2]> {
+    let mut pos = 2;
+    while pos + 9 <= data.len() {
+        if data[pos] != 0xff {
+            return None;
+        }
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        // SOF0..SOF15, except DHT, JPG and DAC which share the range
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let height = u16::from_be_bytes([data[pos + 5], data[pos + 6]]);
+            let width = u16::from_be_bytes([data[pos + 7], data[pos + 8]]);
+            return Some([u32::from(width), u32::from(height)]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
 // Trait for handling different image formats
 trait ImageFormatHandler {
     fn log_to_rerun(
@@ -128,6 +299,7 @@ trait ImageFormatHandler {
         rec: &rerun::RecordingStream,
     ) -> Result<(), Box<dyn Error>>;
     fn get_format_name(&self) -> &'static str;
+    fn dimensions(&self) -> [u32; 2];
 }
 
 // Individual format handlers
@@ -157,6 +329,10 @@ impl<'a> ImageFormatHandler for Yuv420Handler<'a> {
     fn get_format_name(&self) -> &'static str {
         "YUV420"
     }
+
+    fn dimensions(&self) -> [u32; 2] {
+        [self.data.width, self.data.height]
+    }
 }
 
 // Note: Removed expensive YUV420 to RGB conversion function
@@ -187,6 +363,10 @@ impl<'a> ImageFormatHandler for Rgb888Handler<'a> {
     fn get_format_name(&self) -> &'static str {
         "RGB888"
     }
+
+    fn dimensions(&self) -> [u32; 2] {
+        [self.data.width, self.data.height]
+    }
 }
 
 struct Rgba8888Handler<'a> {
@@ -214,6 +394,10 @@ impl<'a> ImageFormatHandler for Rgba8888Handler<'a> {
     fn get_format_name(&self) -> &'static str {
         "RGBA8888"
     }
+
+    fn dimensions(&self) -> [u32; 2] {
+        [self.data.width, self.data.height]
+    }
 }
 
 struct Nv12Handler<'a> {
@@ -242,6 +426,10 @@ impl<'a> ImageFormatHandler for Nv12Handler<'a> {
     fn get_format_name(&self) -> &'static str {
         "NV12"
     }
+
+    fn dimensions(&self) -> [u32; 2] {
+        [self.data.width, self.data.height]
+    }
 }
 
 // Helper function to handle any image format
@@ -249,8 +437,10 @@ fn handle_image_format(
     handler: &dyn ImageFormatHandler,
     entity_path: String,
     rec: &rerun::RecordingStream,
+    ctx: &HandlerContext,
 ) -> Result<(), Box<dyn Error>> {
     log::info!("Processing {} image", handler.get_format_name());
+    ctx.image_sizes.record(&entity_path, handler.dimensions());
     handler.log_to_rerun(entity_path, rec)
 }
 
@@ -272,23 +462,24 @@ impl MessageHandler for ImageRawAnyHandler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         // Handle the one-of field properly
         match &message_decoded.image {
             Some(image_raw_any::Image::Rgb888(rgb888)) => {
                 let handler = Rgb888Handler { data: rgb888 };
-                handle_image_format(&handler, entity_path, rec)
+                handle_image_format(&handler, entity_path, rec, ctx)
             }
             Some(image_raw_any::Image::Rgba8888(rgba8888)) => {
                 let handler = Rgba8888Handler { data: rgba8888 };
-                handle_image_format(&handler, entity_path, rec)
+                handle_image_format(&handler, entity_path, rec, ctx)
             }
             Some(image_raw_any::Image::Yuv420(yuv420)) => {
                 let handler = Yuv420Handler { data: yuv420 };
-                handle_image_format(&handler, entity_path, rec)
+                handle_image_format(&handler, entity_path, rec, ctx)
             }
             Some(image_raw_any::Image::Yuv422(_yuv422)) => {
                 log::warn!("YUV422 format not yet implemented");
@@ -300,7 +491,7 @@ impl MessageHandler for ImageRawAnyHandler {
             }
             Some(image_raw_any::Image::Nv12(nv12)) => {
                 let handler = Nv12Handler { data: nv12 };
-                handle_image_format(&handler, entity_path, rec)
+                handle_image_format(&handler, entity_path, rec, ctx)
             }
             None => {
                 Err("No image format found in ImageRawAny message".into())
@@ -327,14 +518,15 @@ impl MessageHandler for ImageYuv420Handler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         let handler = Yuv420Handler {
             data: &message_decoded,
         };
-        handle_image_format(&handler, entity_path, rec)
+        handle_image_format(&handler, entity_path, rec, ctx)
     }
 }
 
@@ -355,14 +547,15 @@ impl MessageHandler for ImageRgb888Handler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         let handler = Rgb888Handler {
             data: &message_decoded,
         };
-        handle_image_format(&handler, entity_path, rec)
+        handle_image_format(&handler, entity_path, rec, ctx)
     }
 }
 
@@ -383,14 +576,15 @@ impl MessageHandler for ImageRgba8888Handler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         let handler = Rgba8888Handler {
             data: &message_decoded,
         };
-        handle_image_format(&handler, entity_path, rec)
+        handle_image_format(&handler, entity_path, rec, ctx)
     }
 }
 
@@ -411,41 +605,402 @@ impl MessageHandler for Boxes2DAxisAlignedHandler {
         &self,
         sample: &zenoh::sample::Sample,
         rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
     ) -> Result<(), Box<dyn Error>> {
         let message_decoded = self.encoder.decode(&sample.payload().to_bytes())?;
-        let (entity_path, _header_time) = process_header_and_set_time(&message_decoded.header, rec);
-
-        if message_decoded.boxes.is_empty() {
-            log::info!("No boxes to log in Boxes2DAxisAligned message");
-            return Ok(());
-        }
+        let entity_path = process_header_and_set_time(&message_decoded.header, sample, ctx, rec)?;
 
         // Collect all box geometries for batch logging
         let mut box_centers = Vec::new();
         let mut box_half_sizes = Vec::new();
-
-        for box_item in message_decoded.boxes.iter() {
+        let mut class_ids = Vec::new();
+        let mut labels = Vec::new();
+        let mut track_ids = Vec::new();
+
+        let selected = select_detections(
+            &message_decoded.boxes,
+            |box_item| box_item.confidence,
+            |box_item| u16::try_from(box_item.class_id).ok(),
+            ctx,
+        );
+        let Some([scale_x, scale_y]) = pixel_scale(
+            ctx,
+            selected
+                .iter()
+                .filter_map(|box_item| box_item.geometry.as_ref())
+                .flat_map(|geometry| [geometry.x, geometry.y, geometry.width, geometry.height]),
+        ) else {
+            // Rejected at startup unless an image entity path is configured
+            if let Some(image_entity_path) = &ctx.detections.image_entity_path {
+                ctx.image_sizes.report_missing(image_entity_path);
+            }
+            return Ok(());
+        };
+        for box_item in selected {
             if let Some(geometry) = &box_item.geometry {
                 // Convert box geometry to rerun format
                 // Assuming geometry has fields like x, y, width, height
-                let center_x = geometry.x + geometry.width / 2.0;
-                let center_y = geometry.y + geometry.height / 2.0;
+                let (x, y) = (geometry.x * scale_x, geometry.y * scale_y);
+                let (width, height) = (geometry.width * scale_x, geometry.height * scale_y);
+                let center_x = x + width / 2.0;
+                let center_y = y + height / 2.0;
 
                 box_centers.push([center_x, center_y]);
-                box_half_sizes.push([geometry.width / 2.0, geometry.height / 2.0]);
+                box_half_sizes.push([width / 2.0, height / 2.0]);
+
+                // Ids out of the u16 range can't name a class and are left without one
+                let class_id = u16::try_from(box_item.class_id).ok();
+                class_ids.push(class_id);
+                let class_name = class_id.map(|class_id| {
+                    ctx.label_map
+                        .and_then(|label_map| label_map.name(class_id))
+                        .map(str::to_string)
+                        .unwrap_or_else(|| class_id.to_string())
+                });
+                labels.push(match class_name {
+                    Some(class_name) => format!("{} {:.2}", class_name, box_item.confidence),
+                    None => format!("{:.2}", box_item.confidence),
+                });
+                // Tracked boxes carry their identity as reference id of their own header
+                track_ids.push(
+                    box_item
+                        .header
+                        .as_ref()
+                        .map(|header| header.reference_id)
+                        .filter(|track_id| *track_id != 0),
+                );
             }
         }
 
         // Log all boxes in one batch call using Boxes2D
-        if !box_centers.is_empty() {
-            let _box_count = box_centers.len();
-            rec.log(
-                entity_path,
-                &rerun::Boxes2D::from_centers_and_half_sizes(box_centers, box_half_sizes),
-            ).map_err(|e| Box::new(e) as Box<dyn Error>)
-        } else {
-            Ok(())
+        if box_centers.is_empty() {
+            return log_detections(rec, ctx, entity_path, None::<rerun::Boxes2D>);
+        }
+        let mut boxes = rerun::Boxes2D::from_centers_and_half_sizes(box_centers, box_half_sizes)
+            .with_labels(labels)
+            .with_show_labels(true);
+        // Class ids can only be logged for all boxes or none
+        let all_classes: Option<Vec<u16>> = class_ids.iter().copied().collect();
+        let has_tracks = track_ids.iter().any(Option::is_some);
+        if let Some(all_classes) = &all_classes {
+            boxes = boxes.with_class_ids(all_classes.iter().copied());
         }
+        // Colors are left to the annotation context of the class ids unless boxes are tracked
+        // or lack a class
+        if has_tracks || all_classes.is_none() {
+            boxes = boxes.with_colors(track_ids.iter().zip(&class_ids).map(
+                |(track_id, class_id)| {
+                    match (track_id, class_id) {
+                        (Some(track_id), _) => stable_color(*track_id),
+                        (None, Some(class_id)) => ctx
+                            .label_map
+                            .and_then(|label_map| label_map.color(*class_id))
+                            .unwrap_or_else(|| stable_color(u64::from(*class_id))),
+                        (None, None) => rerun::Color::from_rgb(128, 128, 128),
+                    }
+                },
+            ));
+        }
+        log_detections(rec, ctx, entity_path, Some(boxes))
+    }
+}
+
+/// Distinct color that stays the same for an id across messages, spreading consecutive ids
+/// over the hue circle by the golden ratio.
+fn stable_color(id: u64) -> rerun::Color {
+    let hue = (id as f64 * 0.618_033_988_749_895).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u8 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    // Slightly desaturated for legibility of labels drawn on top
+    let channel = |value: f64| (55.0 + value * 200.0) as u8;
+    rerun::Color::from_rgb(channel(r), channel(g), channel(b))
+}
+
+/// Factors scaling box coordinates to pixels according to the topic's coordinate mode.
+/// Normalized coordinates are scaled by the most recent image at the associated entity path,
+/// or the configured image size until one was logged. `None` while no image size is known
+/// to scale normalized coordinates.
+fn pixel_scale(
+    ctx: &HandlerContext,
+    coordinates: impl IntoIterator<Item = f32>,
+) -> Option<[f32; 2]> {
+    let config = ctx.detections;
+    let image_size = config
+        .image_entity_path
+        .as_deref()
+        .and_then(|entity_path| ctx.image_sizes.get(entity_path))
+        .or(config.image_size);
+
+    let Some([width, height]) = image_size else {
+        return match config.coordinates {
+            BoxCoordinates::Normalized => None,
+            BoxCoordinates::Pixel | BoxCoordinates::Auto => Some([1.0, 1.0]),
+        };
+    };
+    let normalized = match config.coordinates {
+        BoxCoordinates::Pixel => false,
+        BoxCoordinates::Normalized => true,
+        BoxCoordinates::Auto => coordinates
+            .into_iter()
+            .all(|coordinate| (0.0..=1.0).contains(&coordinate)),
+    };
+    Some(if normalized {
+        [width as f32, height as f32]
+    } else {
+        [1.0, 1.0]
+    })
+}
+
+/// Detections passing the topic's confidence threshold and class selection, the most
+/// confident first when capped to the top K.
+fn select_detections<'a, T>(
+    detections: &'a [T],
+    confidence: impl Fn(&T) -> f32,
+    class_id: impl Fn(&T) -> Option<u16>,
+    ctx: &HandlerContext,
+) -> Vec<&'a T> {
+    let config = ctx.detections;
+    // Detections without a valid class id only pass when no classes are selected
+    let selects_class = |class: Option<u16>| {
+        config.classes.is_empty()
+            || class.is_some_and(|class| {
+                config.classes.iter().any(|selector| match selector {
+                    ClassSelector::Id(id) => *id == class,
+                    // Names are resolved to ids when the dispatcher is created
+                    ClassSelector::Name(_) => false,
+                })
+            })
+    };
+
+    let mut selected: Vec<&T> = detections
+        .iter()
+        .filter(|detection| {
+            config
+                .min_confidence
+                .is_none_or(|min_confidence| confidence(detection) >= min_confidence)
+        })
+        .filter(|detection| selects_class(class_id(detection)))
+        .collect();
+    if let Some(top_k) = config.top_k {
+        selected.sort_by(|a, b| confidence(b).total_cmp(&confidence(a)));
+        selected.truncate(top_k);
+    }
+    selected
+}
+
+/// Log the detections of a message, or clear the previous ones if it has none so they do
+/// not linger in the viewer after objects leave the frame. The annotation context of the
+/// topic's label map is logged to the entity path first if it isn't yet.
+fn log_detections(
+    rec: &rerun::RecordingStream,
+    ctx: &HandlerContext,
+    entity_path: String,
+    detections: Option<impl rerun::AsComponents>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(label_map) = ctx.label_map {
+        ctx.annotations.register(rec, &entity_path, label_map)?;
+    }
+    match detections {
+        Some(detections) => rec.log(entity_path, &detections),
+        // Flat, so children like the latency scalar are kept
+        None => rec.log(entity_path, &rerun::Clear::flat()),
+    }
+    .map_err(|e| Box::new(e) as Box<dyn Error>)
+}
+
+// Minimal protobuf wire format reader for messages without a registered schema
+#[derive(Debug, PartialEq)]
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    LengthDelimited(&'a [u8]),
+    Fixed32(u32),
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// Returns the parsed fields and the offset up to which the payload was valid
+fn read_wire_fields(bytes: &[u8]) -> (Vec<(u64, WireValue<'_>)>, usize) {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let field = (|| {
+            let key = read_varint(bytes, &mut pos)?;
+            let value = match key & 0x7 {
+                0 => WireValue::Varint(read_varint(bytes, &mut pos)?),
+                1 => {
+                    let raw = bytes.get(pos..pos + 8)?;
+                    pos += 8;
+                    WireValue::Fixed64(u64::from_le_bytes(raw.try_into().ok()?))
+                }
+                2 => {
+                    let len = read_varint(bytes, &mut pos)? as usize;
+                    let raw = bytes.get(pos..pos.checked_add(len)?)?;
+                    pos += len;
+                    WireValue::LengthDelimited(raw)
+                }
+                5 => {
+                    let raw = bytes.get(pos..pos + 4)?;
+                    pos += 4;
+                    WireValue::Fixed32(u32::from_le_bytes(raw.try_into().ok()?))
+                }
+                _ => return None,
+            };
+            Some((key >> 3, value))
+        })();
+        match field {
+            Some(field) => fields.push(field),
+            None => return (fields, start),
+        }
+    }
+    (fields, pos)
+}
+
+// make87_messages carry their header as field 1, which lets it be decoded without knowing
+// the message type
+fn decode_header_generic(payload: &[u8]) -> Option<Header> {
+    let (fields, _) = read_wire_fields(payload);
+    fields.iter().find_map(|(number, value)| match value {
+        WireValue::LengthDelimited(raw) if *number == 1 => {
+            ProtobufEncoder::<Header>::new().decode(raw).ok()
+        }
+        _ => None,
+    })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn dump_proto_wire(payload: &[u8], max_dump_bytes: usize) -> String {
+    let (fields, valid_len) = read_wire_fields(payload);
+    let mut dump = String::new();
+    for (number, value) in &fields {
+        let rendered = match value {
+            WireValue::Varint(v) => format!("{}: varint {}", number, v),
+            WireValue::Fixed64(v) => format!("{}: fixed64 {} ({})", number, v, f64::from_bits(*v)),
+            WireValue::Fixed32(v) => format!("{}: fixed32 {} ({})", number, v, f32::from_bits(*v)),
+            WireValue::LengthDelimited(raw) => {
+                let shown = &raw[..raw.len().min(max_dump_bytes)];
+                let ellipsis = if raw.len() > shown.len() { " ..." } else { "" };
+                format!(
+                    "{}: bytes[{}] {}{}",
+                    number,
+                    raw.len(),
+                    to_hex(shown),
+                    ellipsis
+                )
+            }
+        };
+        dump.push_str(&rendered);
+        dump.push('\n');
+    }
+    if valid_len < payload.len() {
+        let rest = &payload[valid_len..];
+        let shown = &rest[..rest.len().min(max_dump_bytes)];
+        dump.push_str(&format!(
+            "not protobuf from offset {}: {}",
+            valid_len,
+            to_hex(shown)
+        ));
+    }
+    dump
+}
+
+fn child_entity_path(parent: &str, child: &str) -> String {
+    format!("{}/{}", parent.trim_end_matches('/'), child)
+}
+
+// Handler for message types without a registered handler: ships payload size and rate,
+// a wire format dump and the header if it can be decoded generically
+pub struct FallbackHandler {
+    message_type: String,
+    max_dump_bytes: usize,
+    // Exponential moving average of the message rate in Hz, with the last arrival
+    rate: Mutex<(Option<Instant>, f64)>,
+}
+
+impl FallbackHandler {
+    pub fn new(message_type: &str, config: &FallbackConfig) -> Self {
+        Self {
+            message_type: message_type.to_string(),
+            max_dump_bytes: config.max_dump_bytes,
+            rate: Mutex::new((None, 0.0)),
+        }
+    }
+
+    fn update_rate(&self) -> f64 {
+        let mut rate = self.rate.lock().unwrap();
+        let now = Instant::now();
+        if let Some(last) = rate.0 {
+            let interval = now.duration_since(last).as_secs_f64();
+            if interval > 0.0 {
+                let instant_rate = 1.0 / interval;
+                rate.1 = if rate.1 == 0.0 {
+                    instant_rate
+                } else {
+                    0.9 * rate.1 + 0.1 * instant_rate
+                };
+            }
+        }
+        rate.0 = Some(now);
+        rate.1
+    }
+}
+
+impl MessageHandler for FallbackHandler {
+    fn handle_message(
+        &self,
+        sample: &zenoh::sample::Sample,
+        rec: &rerun::RecordingStream,
+        ctx: &HandlerContext,
+    ) -> Result<(), Box<dyn Error>> {
+        let payload = sample.payload().to_bytes();
+        let header = decode_header_generic(&payload);
+        let entity_path = process_header_and_set_time(&header, sample, ctx, rec)?;
+        let rate = self.update_rate();
+
+        rec.log(
+            child_entity_path(&entity_path, "payload_size"),
+            &rerun::Scalars::single(payload.len() as f64),
+        ).map_err(|e| Box::new(e) as Box<dyn Error>)?;
+        rec.log(
+            child_entity_path(&entity_path, "rate_hz"),
+            &rerun::Scalars::single(rate),
+        ).map_err(|e| Box::new(e) as Box<dyn Error>)?;
+
+        let mut text = format!("{} ({} bytes)\n", self.message_type, payload.len());
+        if let Some(header) = &header {
+            text.push_str(&format!("header: {:?}\n", header));
+        }
+        text.push_str(&dump_proto_wire(&payload, self.max_dump_bytes));
+        rec.log(
+            entity_path,
+            &rerun::TextLog::new(text).with_level(rerun::TextLogLevel::DEBUG),
+        ).map_err(|e| Box::new(e) as Box<dyn Error>)
     }
 }
 
@@ -453,12 +1008,14 @@ type HandlerFactory = fn() -> Box<dyn MessageHandler>;
 
 pub struct MessageTypeRegistry {
     handlers: HashMap<&'static str, HandlerFactory>,
+    fallback: Option<FallbackConfig>,
 }
 
 impl MessageTypeRegistry {
     pub fn new() -> Self {
         let mut registry = Self {
             handlers: HashMap::new(),
+            fallback: None,
         };
 
         // Register message types with their corresponding handlers
@@ -489,23 +1046,427 @@ impl MessageTypeRegistry {
         registry
     }
 
+    /// Ship unregistered message types with a `FallbackHandler` instead of rejecting them
+    pub fn with_fallback(mut self, config: FallbackConfig) -> Self {
+        self.fallback = config.enabled.then_some(config);
+        self
+    }
+
     fn register(&mut self, message_type: &'static str, factory: HandlerFactory) {
         self.handlers.insert(message_type, factory);
     }
 
-    pub fn create_handler_from_topic_key(
+    pub fn create_handler(&self, message_type: &str) -> Option<Box<dyn MessageHandler>> {
+        match (self.handlers.get(message_type), &self.fallback) {
+            (Some(factory), _) => Some(factory()),
+            (None, Some(fallback)) => Some(Box::new(FallbackHandler::new(message_type, fallback))),
+            (None, None) => None,
+        }
+    }
+
+    pub fn can_handle(&self, message_type: &str) -> bool {
+        self.handlers.contains_key(message_type) || self.fallback.is_some()
+    }
+
+    pub fn extract_message_type_from_topic_key<'a>(&self, topic_key: &'a str) -> Option<&'a str> {
+        TopicKey::parse(topic_key).map(|topic_key| topic_key.message_type)
+    }
+
+    /// Resolve the message type of a sample from, in order, the schema of its zenoh
+    /// encoding, a fully qualified protobuf name in its attachment and its topic key.
+    pub fn resolve_message_type(&self, sample: &zenoh::sample::Sample) -> Option<String> {
+        // Encodings display as `<id>;<schema>`
+        let encoding = sample.encoding().to_string();
+        if let Some(message_type) = encoding
+            .split_once(';')
+            .and_then(|(_, schema)| message_type_from_proto_name(schema))
+        {
+            return Some(message_type);
+        }
+
+        if let Some(message_type) = sample
+            .attachment()
+            .and_then(|attachment| attachment.try_to_string().ok())
+            .and_then(|attachment| message_type_from_proto_name(&attachment))
+        {
+            return Some(message_type);
+        }
+
+        self.extract_message_type_from_topic_key(sample.key_expr().as_str())
+            .map(str::to_string)
+    }
+}
+
+/// Map a fully qualified protobuf name like `make87_messages.image.compressed.ImageJPEG` to
+/// its registry key `image-compressed-ImageJPEG`
+fn message_type_from_proto_name(name: &str) -> Option<String> {
+    let name = name.trim().strip_prefix("make87_messages.")?;
+    Some(name.replace('.', "-"))
+}
+
+/// Normalize a configured message type, given either as registry key or as fully qualified
+/// protobuf name
+pub fn normalize_message_type(name: &str) -> String {
+    message_type_from_proto_name(name).unwrap_or_else(|| {
+        name.trim()
+            .trim_start_matches("make87_messages-")
+            .to_string()
+    })
+}
+
+/// A sample with the wall-clock time it was received at, taken before any queueing.
+pub struct ReceivedSample {
+    pub sample: zenoh::sample::Sample,
+    pub received_at: f64,
+}
+
+impl ReceivedSample {
+    pub fn now(sample: zenoh::sample::Sample) -> Self {
+        Self {
+            sample,
+            received_at: get_current_timestamp_secs(),
+        }
+    }
+}
+
+/// A sample together with the handler that decodes and logs it, so it can be run on any
+/// thread.
+pub struct DecodeJob {
+    handler: Arc<dyn MessageHandler>,
+    entity_paths: Arc<EntityPathRules>,
+    timelines: Arc<TimelineConfig>,
+    log_latency: bool,
+    message_type: String,
+    sample: zenoh::sample::Sample,
+    received_at: f64,
+    sequence: u64,
+    telemetry: Arc<Telemetry>,
+    label_map: Option<Arc<LabelMap>>,
+    detections: Arc<DetectionConfig>,
+    annotations: Arc<Annotations>,
+    image_sizes: Arc<ImageSizes>,
+}
+
+impl DecodeJob {
+    pub fn key_expr(&self) -> &str {
+        self.sample.key_expr().as_str()
+    }
+
+    /// Entity path the sample will be logged to, from its generically decoded header
+    pub fn entity_path(&self) -> String {
+        let header = decode_header_generic(&self.sample.payload().to_bytes());
+        resolve_entity_path(
+            &header,
+            self.key_expr(),
+            &self.message_type,
+            &self.entity_paths,
+        )
+    }
+
+    fn context(&self) -> HandlerContext<'_> {
+        HandlerContext {
+            message_type: &self.message_type,
+            entity_paths: &self.entity_paths,
+            timelines: &self.timelines,
+            log_latency: self.log_latency,
+            received_at: self.received_at,
+            sequence: self.sequence,
+            telemetry: &self.telemetry,
+            label_map: self.label_map.as_ref(),
+            detections: &self.detections,
+            image_sizes: &self.image_sizes,
+            annotations: &self.annotations,
+        }
+    }
+
+    pub fn run(&self, rec: &rerun::RecordingStream) -> Result<(), Box<dyn Error>> {
+        let result = self
+            .handler
+            .handle_message(&self.sample, rec, &self.context());
+        if result.is_err() {
+            self.telemetry.record_decode_error(self.key_expr());
+        }
+        result
+    }
+}
+
+const FILTER_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Detection settings with classes selected by name replaced by their ids in `label_map`.
+/// Names that the label map lacks, or selected without one, are an error.
+fn resolve_class_names(
+    config: &DetectionConfig,
+    label_map: Option<&LabelMap>,
+) -> Result<DetectionConfig, String> {
+    let mut classes = Vec::with_capacity(config.classes.len());
+    for selector in &config.classes {
+        match selector {
+            ClassSelector::Id(id) => classes.push(ClassSelector::Id(*id)),
+            ClassSelector::Name(name) => {
+                let label_map = label_map.ok_or_else(|| {
+                    format!(
+                        "Class '{}' is selected by name but no label_map is set",
+                        name
+                    )
+                })?;
+                let len = classes.len();
+                classes.extend(label_map.ids(name).map(ClassSelector::Id));
+                if classes.len() == len {
+                    return Err(format!("Class '{}' is not in the label map", name));
+                }
+            }
+        }
+    }
+    Ok(DetectionConfig {
+        classes,
+        ..config.clone()
+    })
+}
+
+/// Routes each sample to the handler for the message type in its own key expression, so a
+/// single wildcard subscription can carry several message types. Handlers are instantiated
+/// on first use and cached per message type.
+pub struct MessageDispatcher {
+    registry: Arc<MessageTypeRegistry>,
+    config: SubscriberConfig,
+    entity_paths: Arc<EntityPathRules>,
+    timelines: Arc<TimelineConfig>,
+    filter: SampleFilter,
+    last_filter_report: Instant,
+    // Samples received per topic, for the sequence timeline
+    sequences: HashMap<String, u64>,
+    telemetry: Arc<Telemetry>,
+    label_map: Option<Arc<LabelMap>>,
+    detections: Arc<DetectionConfig>,
+    annotations: Arc<Annotations>,
+    image_sizes: Arc<ImageSizes>,
+    // `None` marks message types that were seen but have no registered handler
+    handlers: HashMap<String, Option<Arc<dyn MessageHandler>>>,
+}
+
+impl MessageDispatcher {
+    pub fn new(
+        ctx: &ShipperContext,
+        config: SubscriberConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let label_map = match &config.label_map {
+            Some(path) => Some(Arc::new(LabelMap::load(Path::new(path))?)),
+            None => None,
+        };
+        let detections = resolve_class_names(&config.detections, label_map.as_deref())?;
+        if matches!(detections.coordinates, BoxCoordinates::Normalized)
+            && detections.image_size.is_none()
+            && detections.image_entity_path.is_none()
+        {
+            return Err("Normalized box coordinates need detections.image_size or \
+                detections.image_entity_path"
+                .into());
+        }
+        Ok(Self {
+            entity_paths: Arc::new(EntityPathRules::new(&config.entity_path)?),
+            timelines: Arc::new(config.timelines.clone()),
+            detections: Arc::new(detections),
+            filter: SampleFilter::new(&config.filter)?,
+            last_filter_report: Instant::now(),
+            sequences: HashMap::new(),
+            registry: ctx.registry.clone(),
+            config,
+            telemetry: ctx.telemetry.clone(),
+            label_map,
+            annotations: ctx.annotations.clone(),
+            image_sizes: ctx.image_sizes.clone(),
+            handlers: HashMap::new(),
+        })
+    }
+
+    /// Log the annotation context of the label map at startup, so class names are known
+    /// before the first message. The context applies to all descendants, so it is logged at
+    /// the longest path known to contain everything this subscriber logs; the first
+    /// detections log it at their own entity path as well.
+    pub fn log_static(
         &self,
-        topic_key: &str,
-    ) -> Option<Box<dyn MessageHandler>> {
-        let message_type = self.extract_message_type_from_topic_key(topic_key)?;
-        let factory = self.handlers.get(message_type)?;
-        Some(factory())
-    }
-
-    fn extract_message_type_from_topic_key<'a>(&self, topic_key: &'a str) -> Option<&'a str> {
-        let re = Regex::new(r".*/.*/.*/make87_messages-([^/]+)/.*").ok()?;
-        re.captures(topic_key)
-            .and_then(|caps| caps.get(1))
-            .map(|m| m.as_str())
+        key_expr: &str,
+        rec: &rerun::RecordingStream,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(label_map) = &self.label_map else {
+            return Ok(());
+        };
+        // Topic values of wildcard subscriptions are only known once messages arrive
+        let topic_key = Some(key_expr)
+            .filter(|key_expr| !key_expr.contains('*'))
+            .and_then(TopicKey::parse);
+        let message_type = match &self.config.message_type {
+            Some(message_type) => Some(normalize_message_type(message_type)),
+            None => self
+                .registry
+                .extract_message_type_from_topic_key(key_expr)
+                .map(str::to_string),
+        };
+        let entity_path = self
+            .entity_paths
+            .known_ancestor(topic_key, message_type.as_deref());
+        self.annotations.register(rec, &entity_path, label_map)?;
+        Ok(())
+    }
+
+    /// Resolve the handler for a sample and apply the filters, without decoding it. Returns
+    /// `None` for samples that are dropped.
+    pub fn prepare(
+        &mut self,
+        received: ReceivedSample,
+    ) -> Result<Option<DecodeJob>, Box<dyn Error>> {
+        let ReceivedSample {
+            sample,
+            received_at,
+        } = received;
+        let topic_key = sample.key_expr().as_str();
+        let sequence = self.sequences.entry(topic_key.to_string()).or_default();
+        let sequence = std::mem::replace(sequence, *sequence + 1);
+        let message_type = match &self.config.message_type {
+            Some(message_type) => normalize_message_type(message_type),
+            None => match self.registry.resolve_message_type(&sample) {
+                Some(message_type) => message_type,
+                None => {
+                    self.telemetry.record_decode_error(topic_key);
+                    return Err(format!("No message type for topic: {}", topic_key).into());
+                }
+            },
+        };
+
+        if !self.passes_filter(&sample, &message_type) {
+            return Ok(None);
+        }
+
+        if !self.handlers.contains_key(&message_type) {
+            let handler = self.registry.create_handler(&message_type).map(Arc::from);
+            let known = handler.is_some();
+            self.handlers.insert(message_type.clone(), handler);
+            if !known {
+                self.telemetry.record_decode_error(topic_key);
+                return Err(format!(
+                    "Unknown message type {} for topic: {}",
+                    message_type, topic_key
+                )
+                .into());
+            }
+        }
+
+        // Unknown type was already reported when first seen
+        let Some(Some(handler)) = self.handlers.get(&message_type) else {
+            return Ok(None);
+        };
+        self.telemetry
+            .record_message(topic_key, sample.payload().len());
+        Ok(Some(DecodeJob {
+            handler: handler.clone(),
+            entity_paths: self.entity_paths.clone(),
+            timelines: self.timelines.clone(),
+            log_latency: self.config.latency,
+            message_type,
+            sample,
+            received_at,
+            sequence,
+            telemetry: self.telemetry.clone(),
+            label_map: self.label_map.clone(),
+            detections: self.detections.clone(),
+            annotations: self.annotations.clone(),
+            image_sizes: self.image_sizes.clone(),
+        }))
+    }
+
+    // Runs before any decoding work; entity paths only need the generically decoded header
+    fn passes_filter(&mut self, sample: &zenoh::sample::Sample, message_type: &str) -> bool {
+        let mut passes = self.filter.allows_message_type(message_type);
+        if passes && self.filter.filters_entity_paths() {
+            let header = decode_header_generic(&sample.payload().to_bytes());
+            let entity_path = resolve_entity_path(
+                &header,
+                sample.key_expr().as_str(),
+                message_type,
+                &self.entity_paths,
+            );
+            passes = self.filter.allows_entity_path(&entity_path);
+        }
+
+        if self.last_filter_report.elapsed() >= FILTER_REPORT_INTERVAL {
+            self.last_filter_report = Instant::now();
+            if let Some(summary) = self.filter.summary() {
+                log::info!(
+                    "Samples dropped by filters on {}: {}",
+                    sample.key_expr(),
+                    summary
+                );
+            }
+        }
+        passes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jpeg_dimensions_skip_segments_before_the_frame_header() {
+        let mut jpeg = vec![0xff, 0xd8];
+        // APP0 and DHT segments precede the frame header
+        jpeg.extend([0xff, 0xe0, 0x00, 0x04, 0x00, 0x00]);
+        jpeg.extend([0xff, 0xc4, 0x00, 0x03, 0x00]);
+        // SOF0 of a 640x480 image
+        jpeg.extend([0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xe0, 0x02, 0x80, 0x03]);
+        assert_eq!(jpeg_dimensions(&jpeg), Some([640, 480]));
+    }
+
+    #[test]
+    fn jpeg_dimensions_reject_truncated_or_invalid_data() {
+        assert_eq!(jpeg_dimensions(&[0xff, 0xd8, 0xff, 0xc0, 0x00]), None);
+        assert_eq!(
+            jpeg_dimensions(&[0xff, 0xd8, 0x00, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xe0, 0x02, 0x80]),
+            None
+        );
+    }
+
+    #[test]
+    fn read_wire_fields_parses_all_wire_types() {
+        let mut payload = vec![0x08, 0x96, 0x01];
+        payload.extend([0x12, 0x02, b'h', b'i']);
+        payload.extend([0x1d, 0x01, 0x00, 0x00, 0x00]);
+        payload.extend([0x21, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        let (fields, valid_len) = read_wire_fields(&payload);
+        assert_eq!(
+            fields,
+            vec![
+                (1, WireValue::Varint(150)),
+                (2, WireValue::LengthDelimited(b"hi")),
+                (3, WireValue::Fixed32(1)),
+                (4, WireValue::Fixed64(2)),
+            ]
+        );
+        assert_eq!(valid_len, payload.len());
+    }
+
+    #[test]
+    fn read_wire_fields_stops_at_a_truncated_field() {
+        let payload = [0x08, 0x01, 0x12, 0x05, b'h', b'i'];
+        let (fields, valid_len) = read_wire_fields(&payload);
+        assert_eq!(fields, vec![(1, WireValue::Varint(1))]);
+        assert_eq!(valid_len, 2);
+    }
+
+    #[test]
+    fn class_names_require_a_label_map() {
+        let config = DetectionConfig {
+            classes: vec![ClassSelector::Id(3), ClassSelector::Name("car".to_string())],
+            ..Default::default()
+        };
+        assert!(resolve_class_names(&config, None).is_err());
+
+        let config = DetectionConfig {
+            classes: vec![ClassSelector::Id(3)],
+            ..Default::default()
+        };
+        let resolved = resolve_class_names(&config, None).unwrap();
+        assert!(matches!(resolved.classes[..], [ClassSelector::Id(3)]));
     }
 }
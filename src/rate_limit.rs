@@ -0,0 +1,176 @@
+use crate::config::SubscriberConfig;
+use crate::message_handlers::ReceivedSample;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Anything rate limited per topic
+pub trait TopicItem {
+    fn topic(&self) -> &str;
+}
+
+impl TopicItem for ReceivedSample {
+    fn topic(&self) -> &str {
+        self.sample.key_expr().as_str()
+    }
+}
+
+struct TopicRate<T> {
+    seen: u64,
+    last_shipped: Option<Instant>,
+    // Newest sample held back until the next slot, replacing any older one
+    pending: Option<T>,
+}
+
+impl<T> Default for TopicRate<T> {
+    fn default() -> Self {
+        Self {
+            seen: 0,
+            last_shipped: None,
+            pending: None,
+        }
+    }
+}
+
+/// Per-topic decimation and rate limiting of samples before they are decoded.
+///
+/// Every `keep_every_nth` sample of a topic is considered, and at most one of those is
+/// shipped per `1 / max_rate_hz`. Samples arriving within that interval are held back with
+/// latest-wins semantics, so the sample shipped at the next slot is always the newest one.
+pub struct RateLimiter<T = ReceivedSample> {
+    min_interval: Option<Duration>,
+    keep_every_nth: u64,
+    topics: HashMap<String, TopicRate<T>>,
+}
+
+impl<T: TopicItem> RateLimiter<T> {
+    pub fn new(config: &SubscriberConfig) -> Self {
+        Self {
+            min_interval: config
+                .max_rate_hz
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            keep_every_nth: config.keep_every_nth.unwrap_or(1).max(1),
+            topics: HashMap::new(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.min_interval.is_some() || self.keep_every_nth > 1
+    }
+
+    /// Returns the sample if it should be shipped right away
+    pub fn offer(&mut self, sample: T) -> Option<T> {
+        if !self.is_enabled() {
+            return Some(sample);
+        }
+
+        let topic = self.topics.entry(sample.topic().to_string()).or_default();
+        topic.seen += 1;
+        if !topic.seen.is_multiple_of(self.keep_every_nth) {
+            return None;
+        }
+
+        let Some(min_interval) = self.min_interval else {
+            return Some(sample);
+        };
+        let now = Instant::now();
+        match topic.last_shipped {
+            Some(last_shipped) if now.duration_since(last_shipped) < min_interval => {
+                topic.pending = Some(sample);
+                None
+            }
+            _ => {
+                topic.last_shipped = Some(now);
+                topic.pending = None;
+                Some(sample)
+            }
+        }
+    }
+
+    /// Earliest time a held back sample is due
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let min_interval = self.min_interval?;
+        self.topics
+            .values()
+            .filter(|topic| topic.pending.is_some())
+            .filter_map(|topic| topic.last_shipped)
+            .map(|last_shipped| last_shipped + min_interval)
+            .min()
+    }
+
+    /// Held back samples whose slot has come
+    pub fn take_due(&mut self) -> Vec<T> {
+        let Some(min_interval) = self.min_interval else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        self.topics
+            .values_mut()
+            .filter(|topic| {
+                topic
+                    .last_shipped
+                    .is_some_and(|last_shipped| now.duration_since(last_shipped) >= min_interval)
+            })
+            .filter_map(|topic| {
+                let sample = topic.pending.take()?;
+                topic.last_shipped = Some(now);
+                Some(sample)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item(&'static str, u32);
+
+    impl TopicItem for Item {
+        fn topic(&self) -> &str {
+            self.0
+        }
+    }
+
+    fn limiter(max_rate_hz: Option<f64>, keep_every_nth: Option<u64>) -> RateLimiter<Item> {
+        RateLimiter::new(&SubscriberConfig {
+            max_rate_hz,
+            keep_every_nth,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn passes_everything_when_disabled() {
+        let mut limiter = limiter(None, None);
+        assert!((0..5).all(|i| limiter.offer(Item("a", i)).is_some()));
+        assert_eq!(limiter.next_deadline(), None);
+    }
+
+    #[test]
+    fn keeps_every_nth_sample_per_topic() {
+        let mut limiter = limiter(None, Some(3));
+        let shipped: Vec<u32> = (1..=7)
+            .filter_map(|i| limiter.offer(Item("a", i)))
+            .map(|item| item.1)
+            .collect();
+        assert_eq!(shipped, vec![3, 6]);
+        // Other topics are counted separately
+        assert!(limiter.offer(Item("b", 1)).is_none());
+    }
+
+    #[test]
+    fn ships_the_latest_held_back_sample_at_the_next_slot() {
+        let mut limiter = limiter(Some(20.0), None);
+        assert_eq!(limiter.offer(Item("a", 1)).map(|item| item.1), Some(1));
+        assert!(limiter.offer(Item("a", 2)).is_none());
+        assert!(limiter.offer(Item("a", 3)).is_none());
+        assert!(limiter.take_due().is_empty());
+
+        let deadline = limiter.next_deadline().unwrap();
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        let due: Vec<u32> = limiter.take_due().into_iter().map(|item| item.1).collect();
+        assert_eq!(due, vec![3]);
+        assert_eq!(limiter.next_deadline(), None);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::config::ServerConfig;
+use crate::connection::recording_builder;
+use std::error::Error;
+
+/// Build a recording stream that hosts its own gRPC server for viewers to connect to,
+/// optionally together with the bundled web viewer.
+pub fn recording_stream(
+    config: &ServerConfig,
+    system_id: &str,
+) -> Result<rerun::RecordingStream, Box<dyn Error + Send + Sync>> {
+    let memory_limit = rerun::MemoryLimit::parse(&config.memory_limit).map_err(|e| {
+        format!(
+            "Invalid server memory_limit '{}': {}",
+            config.memory_limit, e
+        )
+    })?;
+    // The web viewer runs in the browser, so it needs an address reachable from there
+    let advertised_host = match (&config.advertised_host, config.web_viewer) {
+        (Some(host), _) => host.as_str(),
+        (None, true) => {
+            return Err("server.advertised_host is required when the web viewer is enabled".into())
+        }
+        (None, false) => config.bind_ip.as_str(),
+    };
+
+    let rec = recording_builder(system_id).serve_grpc_opts(
+        &config.bind_ip,
+        config.grpc_port,
+        memory_limit,
+    )?;
+
+    let grpc_url = format!(
+        "rerun+http://{}:{}/proxy",
+        advertised_host, config.grpc_port
+    );
+    log::info!("Serving Rerun gRPC on {}", grpc_url);
+
+    if config.web_viewer {
+        rerun::serve_web_viewer(rerun::web_viewer::WebViewerConfig {
+            bind_ip: config.bind_ip.clone(),
+            web_port: config.web_viewer_port.to_string().parse()?,
+            connect_to: Some(grpc_url),
+            open_browser: false,
+            ..Default::default()
+        })?
+        .detach();
+        log::info!(
+            "Serving web viewer on http://{}:{}",
+            advertised_host,
+            config.web_viewer_port
+        );
+    }
+
+    Ok(rec)
+}
@@ -0,0 +1,257 @@
+use crate::config::SpoolConfig;
+use crate::connection::transport_stream;
+use crate::file_sink::message_size;
+use rerun::external::re_log_encoding::decoder::Decoder;
+use rerun::log::LogMsg;
+use rerun::sink::{FileSink, LogSink};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SEGMENT_PREFIX: &str = "segment-";
+
+struct OpenSegment {
+    sink: FileSink,
+    path: PathBuf,
+    // Bytes sent so far; the file on disk lags behind as the sink writes in the background
+    bytes: u64,
+}
+
+struct SpoolState {
+    // Finished segments and their sizes, oldest first
+    closed: VecDeque<(PathBuf, u64)>,
+    current: Option<OpenSegment>,
+    next_segment: u64,
+    // Replayed at the start of every segment so each one can be decoded on its own
+    store_info: Option<LogMsg>,
+    active: bool,
+}
+
+impl SpoolState {
+    fn close_current(&mut self) {
+        if let Some(segment) = self.current.take() {
+            // Dropping the file sink finishes the segment
+            drop(segment.sink);
+            let size = fs::metadata(&segment.path).map_or(0, |metadata| metadata.len());
+            self.closed.push_back((segment.path, size));
+        }
+    }
+}
+
+/// Bounded on-disk spool of `.rrd` segments written while the viewer is unreachable.
+///
+/// The viewer transport of the recording stream is redirected into a sink writing one
+/// segment at a time, so a tee into local files keeps recording meanwhile. Segments
+/// are rotated at `segment_size_bytes` and the oldest ones are deleted once the spool
+/// exceeds `max_size_bytes`, so the most recent data is kept. Segments left over from a
+/// previous run are replayed once the viewer is reachable.
+pub struct Spool {
+    config: SpoolConfig,
+    state: Arc<Mutex<SpoolState>>,
+}
+
+impl Spool {
+    pub fn new(config: SpoolConfig) -> std::io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+
+        let mut leftovers: Vec<(u64, PathBuf, u64)> = Vec::new();
+        for entry in fs::read_dir(&config.directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let index = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+                .and_then(|name| name.strip_suffix(".rrd"))
+                .and_then(|index| index.parse::<u64>().ok());
+            if let Some(index) = index {
+                leftovers.push((index, path, entry.metadata()?.len()));
+            }
+        }
+        leftovers.sort();
+        if !leftovers.is_empty() {
+            log::info!(
+                "Found {} spool segments from a previous run in {}",
+                leftovers.len(),
+                config.directory
+            );
+        }
+
+        let next_segment = leftovers.last().map_or(0, |(index, _, _)| index + 1);
+        Ok(Self {
+            config,
+            state: Arc::new(Mutex::new(SpoolState {
+                closed: leftovers
+                    .into_iter()
+                    .map(|(_, path, size)| (path, size))
+                    .collect(),
+                current: None,
+                next_segment,
+                store_info: None,
+                active: false,
+            })),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.state.lock().unwrap().active
+    }
+
+    /// Whether finished segments wait to be replayed
+    pub fn has_pending(&self) -> bool {
+        !self.state.lock().unwrap().closed.is_empty()
+    }
+
+    /// Start spooling what `rec` sends to the viewer unless already spooling
+    pub fn on_disconnected(&mut self, rec: &rerun::RecordingStream) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.active {
+                return;
+            }
+            state.active = true;
+        }
+        transport_stream(rec).set_sink(Box::new(SpoolSink {
+            config: self.config.clone(),
+            state: self.state.clone(),
+        }));
+        log::warn!(
+            "Spooling to {} while the viewer is unreachable",
+            self.config.directory
+        );
+    }
+
+    /// Replay all segments that are no longer written to into the viewer transport of
+    /// `target`, oldest first. Local files teed by `target` already hold the data.
+    pub fn replay_closed(&mut self, target: &rerun::RecordingStream) {
+        let target = transport_stream(target);
+        // The lock is only held to take the next segment, so spooling continues meanwhile
+        loop {
+            let Some((segment, _)) = self.state.lock().unwrap().closed.pop_front() else {
+                break;
+            };
+            replay_segment(&segment, &target);
+        }
+    }
+
+    /// Replay the remaining segments into `target` after the spooling stream was disconnected
+    pub fn finish(&mut self, target: &rerun::RecordingStream) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.active = false;
+            state.close_current();
+        }
+        self.replay_closed(target);
+        log::info!("Spool replayed");
+    }
+}
+
+/// Writes log messages into spool segments, enforcing the segment and spool size limits on
+/// every message.
+struct SpoolSink {
+    config: SpoolConfig,
+    state: Arc<Mutex<SpoolState>>,
+}
+
+impl SpoolSink {
+    fn open_segment(&self, state: &mut SpoolState) -> Option<OpenSegment> {
+        let path = Path::new(&self.config.directory)
+            .join(format!("{}{:06}.rrd", SEGMENT_PREFIX, state.next_segment));
+        state.next_segment += 1;
+
+        let sink = match FileSink::new(&path) {
+            Ok(sink) => sink,
+            Err(e) => {
+                log::error!("Failed to open spool segment {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        if let Some(store_info) = &state.store_info {
+            sink.send(store_info.clone());
+        }
+        Some(OpenSegment {
+            sink,
+            path,
+            bytes: 0,
+        })
+    }
+
+    /// Delete the oldest finished segments while the spool exceeds `max_size_bytes`
+    fn enforce_limit(&self, state: &mut SpoolState, current_size: u64) {
+        let mut total = current_size + state.closed.iter().map(|(_, size)| size).sum::<u64>();
+        while total > self.config.max_size_bytes {
+            let Some((oldest, size)) = state.closed.pop_front() else {
+                break;
+            };
+            total -= size;
+            log::warn!("Spool full, dropping {}", oldest.display());
+            if let Err(e) = fs::remove_file(&oldest) {
+                log::error!("Failed to remove spool segment {}: {}", oldest.display(), e);
+            }
+        }
+    }
+}
+
+impl LogSink for SpoolSink {
+    fn send(&self, msg: LogMsg) {
+        let mut state = self.state.lock().unwrap();
+        if matches!(msg, LogMsg::SetStoreInfo(_)) {
+            state.store_info = Some(msg.clone());
+        }
+
+        // A segment never grows beyond the whole spool, so the active one is capped as well
+        let segment_limit = self
+            .config
+            .segment_size_bytes
+            .min(self.config.max_size_bytes);
+        let mut current_size = state.current.as_ref().map_or(0, |segment| segment.bytes);
+        if current_size >= segment_limit {
+            state.close_current();
+            current_size = 0;
+        }
+        if state.current.is_none() {
+            let segment = self.open_segment(&mut state);
+            state.current = segment;
+        }
+        self.enforce_limit(&mut state, current_size);
+
+        if let Some(segment) = &mut state.current {
+            segment.bytes += message_size(&msg);
+            segment.sink.send(msg);
+        }
+    }
+
+    fn flush_blocking(&self) {
+        if let Some(segment) = &self.state.lock().unwrap().current {
+            segment.sink.flush_blocking();
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+fn replay_segment(path: &Path, target: &rerun::RecordingStream) {
+    let result = (|| -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+        let mut count = 0;
+        for msg in decoder {
+            target.record_msg(msg?);
+            count += 1;
+        }
+        Ok(count)
+    })();
+
+    match result {
+        Ok(count) => log::info!("Replayed {} messages from {}", count, path.display()),
+        // A truncated tail is expected if the segment was cut off mid-write
+        Err(e) => log::warn!("Replay of {} stopped early: {}", path.display(), e),
+    }
+    if let Err(e) = fs::remove_file(path) {
+        log::error!("Failed to remove spool segment {}: {}", path.display(), e);
+    }
+}
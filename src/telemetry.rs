@@ -0,0 +1,167 @@
+use crate::config::{TelemetryConfig, TimeSource};
+use crate::connection::{ConnectionState, SharedRecordingStream};
+use crate::workers::DecodePool;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+struct TopicStats {
+    messages: u64,
+    bytes: u64,
+    decode_errors: u64,
+    time_sources: HashMap<TimeSource, u64>,
+    // Totals at the previous report, for rates
+    reported_messages: u64,
+    reported_bytes: u64,
+}
+
+type Transition = (Option<ConnectionState>, ConnectionState);
+
+/// Health counters of the shipper itself, shared by all tasks and periodically logged into
+/// the recording by `run_telemetry`.
+#[derive(Default)]
+pub struct Telemetry {
+    topics: Mutex<HashMap<String, TopicStats>>,
+    reconnects: AtomicU64,
+    // Last known connection state and the transitions not yet logged
+    connection: Mutex<(Option<ConnectionState>, Vec<Transition>)>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a sample handed over for decoding
+    pub fn record_message(&self, topic: &str, bytes: usize) {
+        let mut topics = self.topics.lock().unwrap();
+        let stats = topics.entry(topic.to_string()).or_default();
+        stats.messages += 1;
+        stats.bytes += bytes as u64;
+    }
+
+    pub fn record_decode_error(&self, topic: &str) {
+        let mut topics = self.topics.lock().unwrap();
+        topics.entry(topic.to_string()).or_default().decode_errors += 1;
+    }
+
+    /// Count which source the time of a message was taken from
+    pub fn record_time_source(&self, topic: &str, source: TimeSource) {
+        let mut topics = self.topics.lock().unwrap();
+        let stats = topics.entry(topic.to_string()).or_default();
+        *stats.time_sources.entry(source).or_default() += 1;
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the current gRPC connection state, remembering it if it changed
+    pub fn record_connection_state(&self, state: ConnectionState) {
+        let mut connection = self.connection.lock().unwrap();
+        let (last, transitions) = &mut *connection;
+        if *last != Some(state) {
+            transitions.push((*last, state));
+            *last = Some(state);
+        }
+    }
+
+    fn log(
+        &self,
+        rec: &rerun::RecordingStream,
+        prefix: &str,
+        elapsed: f64,
+        queue_depth: Option<usize>,
+    ) -> Result<(), rerun::RecordingStreamError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        rec.reset_time();
+        rec.set_timestamp_secs_since_epoch("header_time", now);
+
+        let mut topics = self.topics.lock().unwrap();
+        for (topic, stats) in topics.iter_mut() {
+            let path = format!("{}/topics/{}", prefix, topic);
+            let messages = stats.messages - stats.reported_messages;
+            let bytes = stats.bytes - stats.reported_bytes;
+            stats.reported_messages = stats.messages;
+            stats.reported_bytes = stats.bytes;
+
+            rec.log(
+                format!("{}/messages_per_sec", path),
+                &rerun::Scalars::single(messages as f64 / elapsed),
+            )?;
+            rec.log(
+                format!("{}/bytes_per_sec", path),
+                &rerun::Scalars::single(bytes as f64 / elapsed),
+            )?;
+            rec.log(
+                format!("{}/decode_errors", path),
+                &rerun::Scalars::single(stats.decode_errors as f64),
+            )?;
+            for (source, count) in &stats.time_sources {
+                rec.log(
+                    format!("{}/time_source/{}", path, source.as_str()),
+                    &rerun::Scalars::single(*count as f64),
+                )?;
+            }
+        }
+        drop(topics);
+
+        rec.log(
+            format!("{}/reconnects", prefix),
+            &rerun::Scalars::single(self.reconnects.load(Ordering::Relaxed) as f64),
+        )?;
+        if let Some(queue_depth) = queue_depth {
+            rec.log(
+                format!("{}/queue_depth", prefix),
+                &rerun::Scalars::single(queue_depth as f64),
+            )?;
+        }
+
+        let transitions = std::mem::take(&mut self.connection.lock().unwrap().1);
+        for (from, to) in transitions {
+            let text = match from {
+                Some(from) => format!("gRPC connection {:?} -> {:?}", from, to),
+                None => format!("gRPC connection {:?}", to),
+            };
+            let level = match to {
+                ConnectionState::Disconnected => rerun::TextLogLevel::WARN,
+                _ => rerun::TextLogLevel::INFO,
+            };
+            rec.log(
+                format!("{}/connection", prefix),
+                &rerun::TextLog::new(text).with_level(level),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Periodically log the shipper's own health under the configured entity path.
+pub async fn run_telemetry(
+    telemetry: Arc<Telemetry>,
+    config: TelemetryConfig,
+    rec: Arc<SharedRecordingStream>,
+    workers: Option<DecodePool>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(config.interval.max(0.1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let prefix = config.entity_path.trim_end_matches('/').to_string();
+    let mut last_report = Instant::now();
+
+    loop {
+        interval.tick().await;
+        let elapsed = last_report.elapsed().as_secs_f64().max(f64::EPSILON);
+        last_report = Instant::now();
+
+        let queue_depth = workers.as_ref().map(DecodePool::queue_depth);
+        if let Err(e) = telemetry.log(&rec.get(), &prefix, elapsed, queue_depth) {
+            log::error!("Failed to log shipper telemetry: {}", e);
+        }
+    }
+}
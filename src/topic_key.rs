@@ -0,0 +1,106 @@
+use std::fmt;
+
+/// Components of a make87 topic key laid out as
+/// `<system>/<node>/<topic>/make87_messages-<Type>/<version>`.
+///
+/// Extra leading segments are kept as part of `system`, extra trailing segments as part
+/// of `version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopicKey<'a> {
+    pub system: &'a str,
+    pub node: &'a str,
+    pub topic: &'a str,
+    pub message_type: &'a str,
+    pub version: &'a str,
+}
+
+const MESSAGE_TYPE_SEGMENT: &str = "/make87_messages-";
+
+impl<'a> TopicKey<'a> {
+    pub fn parse(key: &'a str) -> Option<Self> {
+        let type_start = key.rfind(MESSAGE_TYPE_SEGMENT)?;
+        let (message_type, version) =
+            key[type_start + MESSAGE_TYPE_SEGMENT.len()..].split_once('/')?;
+        let (rest, topic) = key[..type_start].rsplit_once('/')?;
+        let (system, node) = rest.rsplit_once('/')?;
+
+        Some(Self {
+            system,
+            node,
+            topic,
+            message_type,
+            version,
+        })
+    }
+
+    /// Entity path `/<node>/<topic>`, used when a message does not name its own
+    pub fn entity_path(&self) -> String {
+        format!("/{}/{}", self.node, self.topic)
+    }
+}
+
+impl fmt::Display for TopicKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}{}{}/{}",
+            self.system,
+            self.node,
+            self.topic,
+            MESSAGE_TYPE_SEGMENT,
+            self.message_type,
+            self.version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_components() {
+        let key = TopicKey::parse("robot/camera/front/make87_messages-ImageJPEG/v1").unwrap();
+        assert_eq!(
+            key,
+            TopicKey {
+                system: "robot",
+                node: "camera",
+                topic: "front",
+                message_type: "ImageJPEG",
+                version: "v1",
+            }
+        );
+        assert_eq!(key.entity_path(), "/camera/front");
+        assert_eq!(
+            key.to_string(),
+            "robot/camera/front/make87_messages-ImageJPEG/v1"
+        );
+    }
+
+    #[test]
+    fn keeps_extra_segments_in_system_and_version() {
+        let key =
+            TopicKey::parse("site/robot/camera/front/make87_messages-ImageJPEG/v1/raw").unwrap();
+        assert_eq!(key.system, "site/robot");
+        assert_eq!(key.node, "camera");
+        assert_eq!(key.version, "v1/raw");
+    }
+
+    #[test]
+    fn rejects_missing_segments() {
+        // No system
+        assert_eq!(
+            TopicKey::parse("camera/front/make87_messages-ImageJPEG/v1"),
+            None
+        );
+        // No version
+        assert_eq!(
+            TopicKey::parse("robot/camera/front/make87_messages-ImageJPEG"),
+            None
+        );
+        // No message type
+        assert_eq!(TopicKey::parse("robot/camera/front/ImageJPEG/v1"), None);
+        assert_eq!(TopicKey::parse(""), None);
+    }
+}
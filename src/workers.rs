@@ -0,0 +1,67 @@
+use crate::config::WorkerConfig;
+use crate::connection::SharedRecordingStream;
+use crate::message_handlers::DecodeJob;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Pool of blocking workers that decode and log samples concurrently.
+///
+/// Jobs are sharded over the workers by entity path, so messages logged to the same entity
+/// keep their order while large image decodes do not hold up other entities. Cloned
+/// handles share the workers, which finish their queues and exit once all handles are
+/// dropped.
+#[derive(Clone)]
+pub struct DecodePool {
+    queues: Arc<Vec<mpsc::Sender<DecodeJob>>>,
+}
+
+impl DecodePool {
+    pub fn spawn(
+        config: &WorkerConfig,
+        rec: Arc<SharedRecordingStream>,
+    ) -> (Self, Vec<JoinHandle<()>>) {
+        let (queues, workers) = (0..config.count)
+            .map(|_| {
+                let (sender, mut receiver) =
+                    mpsc::channel::<DecodeJob>(config.queue_capacity.max(1));
+                let rec = rec.clone();
+                let worker = tokio::task::spawn_blocking(move || {
+                    while let Some(job) = receiver.blocking_recv() {
+                        if let Err(e) = job.run(&rec.get()) {
+                            log::error!("Error handling message on {}: {}", job.key_expr(), e);
+                        }
+                    }
+                });
+                (sender, worker)
+            })
+            .unzip();
+
+        (
+            Self {
+                queues: Arc::new(queues),
+            },
+            workers,
+        )
+    }
+
+    /// Number of jobs waiting across all workers
+    pub fn queue_depth(&self) -> usize {
+        self.queues
+            .iter()
+            .map(|queue| queue.max_capacity() - queue.capacity())
+            .sum()
+    }
+
+    /// Queue a job on the worker owning its entity path, waiting while that queue is full
+    pub async fn submit(&self, job: DecodeJob) {
+        let mut hasher = DefaultHasher::new();
+        job.entity_path().hash(&mut hasher);
+        let queue = &self.queues[hasher.finish() as usize % self.queues.len()];
+        if queue.send(job).await.is_err() {
+            log::error!("Decode worker stopped, dropping message");
+        }
+    }
+}